@@ -0,0 +1,169 @@
+// compares the work-stealing `ThreadPool` with the design it replaced,
+// where every worker took its jobs from one `Mutex<mpsc::Receiver>`
+// both run the same batches of tiny jobs, and report throughput and the latency
+// from submitting each job to a worker starting it
+// the pool also times every job for `ThreadPool::stats`, which shows up with jobs this small
+//
+// cargo bench --bench pool
+// cargo bench --bench pool -- 8 1000000    (workers, jobs)
+
+use std::{
+    env, hint,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use web_server::ThreadPool;
+
+// the old design, kept here only to measure against
+struct SharedQueuePool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Box<dyn FnOnce() + Send>>>,
+}
+
+impl SharedQueuePool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let message = receiver.lock().unwrap().recv();
+                    match message {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute(&self, f: impl FnOnce() + Send + 'static) {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for SharedQueuePool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+struct Report {
+    elapsed: Duration,
+    // submit-to-start latency of every job, in nanoseconds, sorted
+    latencies: Vec<u64>,
+}
+
+impl Report {
+    fn percentile(&self, p: f64) -> Duration {
+        let index = ((self.latencies.len() - 1) as f64 * p).round() as usize;
+        Duration::from_nanos(self.latencies[index])
+    }
+
+    fn print(&self, name: &str) {
+        let jobs = self.latencies.len() as f64;
+        println!(
+            "{name:<14} {:>12.0} jobs/s   p50 {:>10.1?}   p99 {:>10.1?}   p99.9 {:>10.1?}   max {:>10.1?}",
+            jobs / self.elapsed.as_secs_f64(),
+            self.percentile(0.5),
+            self.percentile(0.99),
+            self.percentile(0.999),
+            self.percentile(1.0),
+        );
+    }
+}
+
+// submits `jobs` tiny jobs to `pool` through `execute`, split between `producers` threads,
+// then drops the pool to wait for all of them
+fn measure<P: Sync>(
+    jobs: usize,
+    producers: usize,
+    pool: P,
+    execute: impl Fn(&P, Box<dyn FnOnce() + Send>) + Sync,
+) -> Report {
+    let epoch = Instant::now();
+    let latencies: Arc<Vec<AtomicU64>> = Arc::new((0..jobs).map(|_| AtomicU64::new(0)).collect());
+
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        for producer in 0..producers {
+            let (pool, execute, latencies) = (&pool, &execute, &latencies);
+
+            scope.spawn(move || {
+                for i in (producer..jobs).step_by(producers) {
+                    let latencies = Arc::clone(latencies);
+                    let submitted = epoch.elapsed();
+
+                    execute(
+                        pool,
+                        Box::new(move || {
+                            let started = epoch.elapsed();
+                            let latency = (started - submitted).as_nanos() as u64;
+                            latencies[i].store(latency, Ordering::Relaxed);
+
+                            // a little work, so the job isn't optimised away entirely
+                            hint::black_box((0..16u64).sum::<u64>());
+                        }),
+                    );
+                }
+            });
+        }
+    });
+
+    drop(pool);
+    let elapsed = start.elapsed();
+
+    let mut latencies: Vec<u64> = latencies
+        .iter()
+        .map(|l| l.load(Ordering::Relaxed))
+        .collect();
+    latencies.sort_unstable();
+
+    Report { elapsed, latencies }
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, skip anything that isn't a number
+    let numbers: Vec<usize> = env::args().filter_map(|arg| arg.parse().ok()).collect();
+    let workers = numbers.first().copied().unwrap_or(4);
+    let jobs = numbers.get(1).copied().unwrap_or(200_000);
+
+    println!(
+        "{jobs} jobs on {workers} workers, {} cpus\n",
+        thread::available_parallelism().map_or(1, |n| n.get())
+    );
+
+    // one producer is how the server uses the pool, the accept loop handing out connections
+    // more of them is where a single shared queue starts to hurt
+    for producers in [1, workers] {
+        println!("{producers} producer(s)");
+
+        for _ in 0..3 {
+            let pool = SharedQueuePool::new(workers);
+            let report = measure(jobs, producers, pool, |pool, job| pool.execute(job));
+            report.print("shared queue");
+
+            let pool = ThreadPool::new(workers);
+            let report = measure(jobs, producers, pool, |pool, job| pool.execute(job));
+            report.print("work stealing");
+        }
+
+        println!();
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{date::UtcDateTime, log, request::Request, response::StatusCode};
+
+// Common Log Format:
+// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326 512`
+// Combined Log Format adds the referer and user agent:
+// `... 200 2326 "http://example.com/" "curl/8.0" 512`
+// both end with the time taken to serve the request, in microseconds, like apache's `%D`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Common,
+    Combined,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            _ => Err(()),
+        }
+    }
+}
+
+// everything the access log records about one request
+pub struct AccessEntry<'a> {
+    pub peer_addr: Option<SocketAddr>,
+    // `None` when the request couldn't be parsed
+    pub request: Option<&'a Request>,
+    pub status: StatusCode,
+    // body bytes sent, not counting the status line and headers
+    pub bytes: u64,
+    pub latency: Duration,
+    pub time: SystemTime,
+}
+
+pub struct AccessLog {
+    format: LogFormat,
+    // every worker writes to the same sink, and a Mutex keeps their lines from interleaving
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(sink: impl Write + Send + 'static, format: LogFormat) -> Self {
+        Self {
+            format,
+            sink: Mutex::new(Box::new(sink)),
+        }
+    }
+
+    pub fn stderr(format: LogFormat) -> Self {
+        Self::new(io::stderr(), format)
+    }
+
+    // appends to `path`, creating it if needed
+    pub fn open(path: &Path, format: LogFormat) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self::new(LineWriter::new(file), format))
+        // `LineWriter` flushes after every line, so the file is always up to date
+    }
+
+    pub fn record(&self, entry: &AccessEntry) {
+        let line = format_entry(self.format, entry);
+
+        let mut sink = self
+            .sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // a panic elsewhere while holding the lock doesn't corrupt a `Write`, so keep logging
+
+        if let Err(err) = writeln!(sink, "{line}") {
+            log::error(format_args!("Failed to write access log: {err}"));
+        }
+    }
+}
+
+fn format_entry(format: LogFormat, entry: &AccessEntry) -> String {
+    let host = entry
+        .peer_addr
+        .map_or_else(|| String::from("-"), |addr| addr.ip().to_string());
+
+    let request_line = match entry.request {
+        Some(request) => {
+            let query = request
+                .query
+                .as_ref()
+                .map_or_else(String::new, |query| format!("?{query}"));
+            format!(
+                "{} {}{} {}",
+                request.method, request.path, query, request.version
+            )
+        }
+        None => String::from("-"),
+    };
+
+    // CLF writes "-" instead of 0 for an empty body
+    let bytes = if entry.bytes == 0 {
+        String::from("-")
+    } else {
+        entry.bytes.to_string()
+    };
+
+    let mut line = format!(
+        "{host} - - [{}] \"{}\" {} {bytes}",
+        UtcDateTime::from_system_time(entry.time).common_log(),
+        escape(&request_line),
+        entry.status.code(),
+    );
+
+    if format == LogFormat::Combined {
+        let header = |name| {
+            entry
+                .request
+                .and_then(|request| request.header(name))
+                .unwrap_or("-")
+        };
+
+        line.push_str(&format!(
+            " \"{}\" \"{}\"",
+            escape(header("Referer")),
+            escape(header("User-Agent"))
+        ));
+    }
+
+    line.push_str(&format!(" {}", entry.latency.as_micros()));
+
+    line
+}
+
+// quotes and control characters would break the line apart, so escape them
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    fn entry(request: Option<&Request>) -> AccessEntry<'_> {
+        AccessEntry {
+            peer_addr: Some("127.0.0.1:54321".parse().unwrap()),
+            request,
+            status: StatusCode::Ok,
+            bytes: 2326,
+            latency: Duration::from_micros(512),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+        }
+    }
+
+    #[test]
+    fn common_format() {
+        let request = Request::new(Method::Get, "/apache_pb.gif?size=2");
+
+        assert_eq!(
+            format_entry(LogFormat::Common, &entry(Some(&request))),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?size=2 HTTP/1.1\" 200 2326 512"
+        );
+    }
+
+    #[test]
+    fn combined_format() {
+        let mut request = Request::new(Method::Get, "/");
+        request.headers.append("Referer", "http://example.com/");
+        request.headers.append("User-Agent", "curl/8.0 \"quoted\"");
+
+        assert_eq!(
+            format_entry(LogFormat::Combined, &entry(Some(&request))),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \
+             \"http://example.com/\" \"curl/8.0 \\\"quoted\\\"\" 512"
+        );
+    }
+
+    #[test]
+    fn unparsed_request_and_empty_body() {
+        let mut entry = entry(None);
+        entry.status = StatusCode::BadRequest;
+        entry.bytes = 0;
+        entry.peer_addr = None;
+
+        assert_eq!(
+            format_entry(LogFormat::Combined, &entry),
+            "- - - [10/Oct/2000:13:55:36 +0000] \"-\" 400 - \"-\" \"-\" 512"
+        );
+    }
+
+    // a sink the test can read back after the log has written to it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_one_line_per_entry() {
+        let buffer = SharedBuffer::default();
+        let access_log = AccessLog::new(buffer.clone(), LogFormat::Common);
+        let request = Request::new(Method::Post, "/submit");
+
+        access_log.record(&entry(Some(&request)));
+        access_log.record(&entry(None));
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"POST /submit HTTP/1.1\""));
+        assert!(lines[1].contains("\"-\""));
+    }
+}
@@ -0,0 +1,261 @@
+use std::io::{self, BufRead, Read, Write};
+
+use crate::request::{read_line, ParseError};
+
+// chunked transfer encoding sends a body as a series of sized pieces, so its length needn't be known up front:
+// ```
+// 5\r\n
+// hello\r\n
+// 7\r\n
+// , world\r\n
+// 0\r\n
+// \r\n
+// ```
+// a chunk of size 0 marks the end, and may be followed by trailer headers before the final blank line
+
+// a chunk size with more hex digits than this can't fit in a u64
+const MAX_SIZE_DIGITS: usize = 16;
+
+// a chunk size line, extensions and all, longer than this is taken as garbage
+const MAX_CHUNK_LINE_SIZE: usize = 1024;
+
+// decodes a chunked body off `reader`, leaving the reader right after the final blank line
+// the decoded body may not be larger than `max_body_size`, however it's split into chunks,
+// and the trailers not larger than `max_head_size`, like the request head
+// each chunk carries at least one byte of data, so the framing around it stays bounded too
+pub fn read_chunked_body(
+    reader: &mut impl BufRead,
+    max_head_size: usize,
+    max_body_size: u64,
+) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+
+    loop {
+        let size = parse_chunk_size(&read_chunk_line(reader)?)?;
+
+        if size == 0 {
+            break;
+        }
+
+        if size > max_body_size - body.len() as u64 {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        let read = reader.take(size).read_to_end(&mut body)?;
+        // reading through `take` means a huge chunk size doesn't allocate memory the client never sends
+        if (read as u64) < size {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        // every chunk's data is followed by a CRLF of its own
+        if !read_chunk_line(reader)?.is_empty() {
+            return Err(ParseError::InvalidChunk);
+        }
+    }
+
+    // trailers are allowed after the last chunk, but nothing here uses them, so skip to the blank line
+    let mut budget = max_head_size;
+    while !read_line(reader, &mut budget)?.is_empty() {}
+
+    Ok(body)
+}
+
+// reads a chunk size line, or the CRLF after a chunk's data, each with a budget of its own
+fn read_chunk_line(reader: &mut impl BufRead) -> Result<Vec<u8>, ParseError> {
+    let mut budget = MAX_CHUNK_LINE_SIZE;
+
+    match read_line(reader, &mut budget) {
+        Err(ParseError::HeadersTooLarge) => Err(ParseError::InvalidChunk),
+        result => result,
+    }
+}
+
+// `1a;name=value` is a chunk of 26 bytes with an extension, which is ignored
+fn parse_chunk_size(line: &[u8]) -> Result<u64, ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidChunk)?;
+    let size = line.split(';').next().unwrap_or_default().trim();
+
+    if size.is_empty()
+        || size.len() > MAX_SIZE_DIGITS
+        || !size.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(ParseError::InvalidChunk);
+    }
+
+    u64::from_str_radix(size, 16).map_err(|_| ParseError::InvalidChunk)
+}
+
+// wraps a writer so that everything written to it goes out as chunks
+// each `write` call becomes one chunk, so put a `BufWriter` in front to avoid lots of tiny ones
+// `finish` must be called at the end to send the terminating zero-sized chunk
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    // payload bytes written so far, not counting the chunk framing
+    written: u64,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+
+        Ok(self.written)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would be mistaken for the end of the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+
+        self.written += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::MAX_HEAD_SIZE;
+
+    fn decode(raw: &str) -> Result<Vec<u8>, ParseError> {
+        read_chunked_body(&mut raw.as_bytes(), MAX_HEAD_SIZE, 1024)
+    }
+
+    #[test]
+    fn decodes_chunks() {
+        assert_eq!(
+            decode("5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n").unwrap(),
+            b"hello, world"
+        );
+        assert_eq!(decode("0\r\n\r\n").unwrap(), b"");
+    }
+
+    #[test]
+    fn ignores_extensions_and_trailers() {
+        assert_eq!(
+            decode("A;name=value\r\n0123456789\r\n0\r\nExpires: never\r\nX-Other: 1\r\n\r\n")
+                .unwrap(),
+            b"0123456789"
+        );
+    }
+
+    #[test]
+    fn leaves_next_request_in_reader() {
+        let mut reader = &b"3\r\nabc\r\n0\r\n\r\nGET / HTTP/1.1\r\n"[..];
+
+        assert_eq!(
+            read_chunked_body(&mut reader, MAX_HEAD_SIZE, 1024).unwrap(),
+            b"abc"
+        );
+        assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn rejects_bad_chunks() {
+        for raw in [
+            "x\r\nabc\r\n0\r\n\r\n",
+            "-3\r\nabc\r\n0\r\n\r\n",
+            "\r\n\r\n",
+            "3\r\nabcdef\r\n0\r\n\r\n",
+            "11111111111111111\r\n",
+        ] {
+            assert!(
+                matches!(decode(raw), Err(ParseError::InvalidChunk)),
+                "{raw:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn framing_is_bounded_per_line() {
+        // lots of small chunks are fine, their framing isn't held against a head sized budget
+        let raw = "1\r\na\r\n".repeat(2000) + "0\r\n\r\n";
+        assert_eq!(
+            read_chunked_body(&mut raw.as_bytes(), MAX_HEAD_SIZE, 4096).unwrap(),
+            [b'a'; 2000]
+        );
+
+        let raw = format!("1;{}\r\na\r\n0\r\n\r\n", "x".repeat(MAX_CHUNK_LINE_SIZE));
+        assert!(matches!(decode(&raw), Err(ParseError::InvalidChunk)));
+
+        // but the trailers are
+        let raw = "0\r\nX-Trailer: 1\r\n\r\n";
+        assert!(matches!(
+            read_chunked_body(&mut raw.as_bytes(), 8, 1024),
+            Err(ParseError::HeadersTooLarge)
+        ));
+    }
+
+    #[test]
+    fn truncated_body() {
+        assert!(matches!(
+            decode("a\r\nshort"),
+            Err(ParseError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            decode("3\r\nabc\r\n"),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn body_too_large() {
+        // the limit covers all chunks together, not each one on its own
+        let mut reader = &b"3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n"[..];
+        assert!(matches!(
+            read_chunked_body(&mut reader, MAX_HEAD_SIZE, 5),
+            Err(ParseError::BodyTooLarge)
+        ));
+
+        let mut reader = &b"ffffffffffffffff\r\n"[..];
+        assert!(matches!(
+            read_chunked_body(&mut reader, MAX_HEAD_SIZE, 5),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn encodes_chunks() {
+        let mut output = Vec::new();
+        let mut writer = ChunkedWriter::new(&mut output);
+
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(&[b'x'; 26]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 31);
+
+        let expected = format!("5\r\nhello\r\n1a\r\n{}\r\n0\r\n\r\n", "x".repeat(26));
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut output = Vec::new();
+        let mut writer = ChunkedWriter::new(&mut output);
+        for line in ["first\n", "second\n", "third\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(
+            read_chunked_body(&mut &output[..], MAX_HEAD_SIZE, 1024).unwrap(),
+            b"first\nsecond\nthird\n"
+        );
+    }
+}
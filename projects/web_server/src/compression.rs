@@ -0,0 +1,539 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    deflate::{adler32, crc32, Deflater},
+    middleware::{Middleware, Next},
+    request::{Method, Request},
+    response::{Body, Response, StatusCode},
+};
+
+// the content codings this server can produce, both DEFLATE with a different wrapper around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    // RFC 1952, a 10 byte header, and the CRC-32 and length of the data at the end
+    Gzip,
+    // RFC 1950, the zlib format, a 2 byte header and the Adler-32 of the data at the end
+    // confusingly called `deflate` in HTTP
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// picks an encoding out of an `Accept-Encoding` header like `gzip, deflate;q=0.5, br`
+// the one with the highest q-value wins, gzip if they're even, and `*` stands for any not named
+// `None` if neither is acceptable, in which case the response is sent as it is
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let (mut gzip, mut deflate, mut any) = (None, None, None);
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim();
+
+        let mut quality = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    // a q-value that can't be read counts as not acceptable
+                    quality = value.trim().parse::<f32>().unwrap_or(0.0);
+                }
+            }
+        }
+
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(quality);
+        } else if coding.eq_ignore_ascii_case("deflate") {
+            deflate = Some(quality);
+        } else if coding == "*" {
+            any = Some(quality);
+        }
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+// compresses everything written to it into `writer` with `encoding`, header and trailer included
+// `finish` has to be called at the end to write the trailer
+pub struct Encoder<W: Write> {
+    deflater: Deflater<W>,
+    encoding: Encoding,
+    // the CRC-32 or Adler-32 of the data so far
+    checksum: u32,
+    // gzip wants the length too, modulo 2^32
+    len: u32,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(mut writer: W, encoding: Encoding) -> io::Result<Self> {
+        let checksum = match encoding {
+            // no file name, no modification time, made on an unknown OS
+            Encoding::Gzip => {
+                writer.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff])?;
+                crc32(0, &[])
+            }
+            // DEFLATE with a 32 KiB window, and a check value that makes the header a multiple of 31
+            Encoding::Deflate => {
+                writer.write_all(&[0x78, 0x01])?;
+                adler32(1, &[])
+            }
+        };
+
+        Ok(Self {
+            deflater: Deflater::new(writer),
+            encoding,
+            checksum,
+            len: 0,
+        })
+    }
+
+    // writes the last block and the trailer, and hands back the writer
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.deflater.finish()?;
+
+        match self.encoding {
+            Encoding::Gzip => {
+                writer.write_all(&self.checksum.to_le_bytes())?;
+                writer.write_all(&self.len.to_le_bytes())?;
+            }
+            Encoding::Deflate => writer.write_all(&self.checksum.to_be_bytes())?,
+        }
+
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.deflater.write(buf)?;
+        let buf = &buf[..written];
+
+        self.checksum = match self.encoding {
+            Encoding::Gzip => crc32(self.checksum, buf),
+            Encoding::Deflate => adler32(self.checksum, buf),
+        };
+        self.len = self.len.wrapping_add(written as u32);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.flush()
+    }
+}
+
+// `data` compressed with `encoding`, all at once
+pub fn compress(data: &[u8], encoding: Encoding) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new(), encoding).expect("writing to a Vec doesn't fail");
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec doesn't fail");
+    encoder.finish().expect("writing to a Vec doesn't fail")
+}
+
+// compresses text responses for clients that say they can take it, with gzip or deflate
+// ```
+// let server = Server::bind("127.0.0.1:7878", 4)?
+//     .with_middleware(Compress::new());
+// ```
+// only bodies of a type that compresses well, like HTML, CSS, JavaScript and JSON, are compressed,
+// and only once they're at least `min_size` bytes, below that the headers take more than is saved
+// a body that was already in memory is sent with a Content-Length, anything else is compressed
+// while it's being sent, with chunked transfer encoding
+pub struct Compress {
+    min_size: u64,
+}
+
+// about where gzip's header, trailer and the lost Content-Length stop being worth it
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+impl Compress {
+    pub fn new() -> Self {
+        Self {
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    // bodies smaller than this are sent as they are, streamed bodies are always compressed
+    pub fn with_min_size(mut self, bytes: u64) -> Self {
+        self.min_size = bytes;
+        self
+    }
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compress {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let encoding = request.header("Accept-Encoding").and_then(negotiate);
+        let is_head = request.method == Method::Head;
+
+        let mut response = next.run(request);
+
+        if !is_compressible(&response) {
+            return response;
+        }
+
+        // caches have to know the same URL can come back compressed or not, depending on this header
+        if !response.headers.has_token("Vary", "Accept-Encoding") {
+            response.headers.append("Vary", "Accept-Encoding");
+        }
+
+        let Some(encoding) = encoding else {
+            return response;
+        };
+
+        // the body of a HEAD response is already gone, but the headers left say how long it was
+        let len = if is_head {
+            response
+                .headers
+                .get("Content-Length")
+                .and_then(|len| len.parse().ok())
+        } else {
+            response.body.len()
+        };
+
+        if len.is_some_and(|len| len < self.min_size) {
+            return response;
+        }
+
+        response.body = match std::mem::take(&mut response.body) {
+            // nothing to compress, only the headers to change the way a GET's would be
+            body if is_head => body,
+            Body::Bytes(bytes) => {
+                let compressed = compress(&bytes, encoding);
+
+                // already compressed data, or data that's just random, only gets bigger
+                if compressed.len() >= bytes.len() {
+                    response.body = Body::Bytes(bytes);
+                    return response;
+                }
+                Body::Bytes(compressed)
+            }
+            Body::Reader { reader, len } => Body::stream(move |writer| {
+                let mut encoder = Encoder::new(writer, encoding)?;
+                let copied = io::copy(&mut reader.take(len), &mut encoder)?;
+
+                if copied < len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("body ended after {copied} of {len} bytes"),
+                    ));
+                }
+
+                encoder.finish()?;
+                Ok(())
+            }),
+            // flushes the stream asks for go through the encoder, so they still reach the client
+            Body::Chunked(produce) => Body::stream(move |writer| {
+                let mut encoder = Encoder::new(writer, encoding)?;
+                produce(&mut encoder)?;
+                encoder.finish()?;
+                Ok(())
+            }),
+        };
+
+        response.headers.set("Content-Encoding", encoding.as_str());
+        // the length is of the compressed body now, worked out when it's written
+        response.headers.remove("Content-Length");
+        // a range would still be cut from the uncompressed body, and a client resuming a download
+        // would splice those bytes onto the compressed ones it already has
+        response.headers.remove("Accept-Ranges");
+
+        // the compressed bytes differ from the ones the ETag was made for, so it can only be a weak
+        // match now, which still answers `If-None-Match` but no longer `If-Range`
+        if let Some(etag) = response.headers.get("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{etag}");
+                response.headers.set("ETag", weak);
+            }
+        }
+
+        // how long the compressed body would be can't be known without making it,
+        // so it's announced the way a compressed file is sent
+        if is_head {
+            response.headers.set("Transfer-Encoding", "chunked");
+        }
+
+        response
+    }
+}
+
+// whether `response` is one to compress, given a client that takes it
+fn is_compressible(response: &Response) -> bool {
+    // a part of a file has its offsets in the uncompressed body, and 304s and the like have no body
+    if !response.status.allows_body() || response.status == StatusCode::PartialContent {
+        return false;
+    }
+
+    if response.headers.contains("Content-Encoding") {
+        return false;
+    }
+
+    let Some(content_type) = response.headers.get("Content-Type") else {
+        return false;
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    match media_type.as_str() {
+        // events are sent one at a time as they happen, compressing them only holds them up
+        "text/event-stream" => false,
+        "application/json"
+        | "application/javascript"
+        | "application/xml"
+        | "application/wasm"
+        | "image/svg+xml" => true,
+        other => other.starts_with("text/") || other.ends_with("+json") || other.ends_with("+xml"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deflate::tests::inflate, router::Router};
+
+    // undoes `Encoder`, checking the header and trailer on the way
+    fn decode(data: &[u8], encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Gzip => {
+                assert_eq!(data[..4], [0x1f, 0x8b, 8, 0], "gzip header");
+                let (inflated, used) = inflate(&data[10..]);
+                let trailer = &data[10 + used..];
+
+                assert_eq!(trailer.len(), 8);
+                assert_eq!(trailer[..4], crc32(0, &inflated).to_le_bytes());
+                assert_eq!(trailer[4..], (inflated.len() as u32).to_le_bytes());
+                inflated
+            }
+            Encoding::Deflate => {
+                assert_eq!(
+                    u16::from_be_bytes([data[0], data[1]]) % 31,
+                    0,
+                    "zlib header"
+                );
+                let (inflated, used) = inflate(&data[2..]);
+
+                assert_eq!(data[2 + used..], adler32(1, &inflated).to_be_bytes());
+                inflated
+            }
+        }
+    }
+
+    fn run(request: &mut Request, response: impl Fn() -> Response) -> Response {
+        let chain: [Box<dyn Middleware>; 1] = [Box::new(Compress::new())];
+        let endpoint = move |_: &Request| response();
+        Next::new(&chain, &endpoint).run(request)
+    }
+
+    fn accepting(encodings: &str) -> Request {
+        let mut request = Request::new(Method::Get, "/");
+        request.headers.set("Accept-Encoding", encodings);
+        request
+    }
+
+    fn page() -> String {
+        "<p>the same paragraph, over and over</p>\n".repeat(100)
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("GZIP ; Q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+
+        for header in ["", "identity", "br", "gzip;q=0", "gzip;q=nope", "*;q=0"] {
+            assert_eq!(negotiate(header), None, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = page();
+
+        for encoding in [Encoding::Gzip, Encoding::Deflate] {
+            let compressed = compress(text.as_bytes(), encoding);
+            assert!(compressed.len() < text.len() / 10);
+            assert_eq!(decode(&compressed, encoding), text.as_bytes());
+
+            assert_eq!(decode(&compress(b"", encoding), encoding), b"");
+        }
+    }
+
+    #[test]
+    fn compresses_text_for_clients_that_accept_it() {
+        let response = run(&mut accepting("gzip, deflate"), || Response::html(page()));
+
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+
+        let body = response.body.as_bytes().unwrap();
+        assert_eq!(decode(body, Encoding::Gzip), page().as_bytes());
+
+        let response = run(&mut accepting("deflate"), || Response::html(page()));
+        assert_eq!(response.headers.get("Content-Encoding"), Some("deflate"));
+        let body = response.body.as_bytes().unwrap();
+        assert_eq!(decode(body, Encoding::Deflate), page().as_bytes());
+    }
+
+    #[test]
+    fn leaves_alone_what_it_shouldnt_compress() {
+        // the client didn't ask, but a cache still has to know it could have
+        let response = run(&mut Request::new(Method::Get, "/"), || {
+            Response::html(page())
+        });
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+
+        // too small to be worth it
+        let response = run(&mut accepting("gzip"), || Response::html("<p>hi</p>"));
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert_eq!(response.body.as_bytes().unwrap(), b"<p>hi</p>");
+
+        // not a type that compresses
+        let response = run(&mut accepting("gzip"), || {
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "image/png")
+                .with_body(page())
+        });
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert!(!response.headers.contains("Vary"));
+
+        // part of a file
+        let response = run(&mut accepting("gzip"), || {
+            Response::html(page()).with_status(StatusCode::PartialContent)
+        });
+        assert!(!response.headers.contains("Content-Encoding"));
+
+        // compressed by the handler already
+        let response = run(&mut accepting("gzip"), || {
+            Response::html(page()).with_header("Content-Encoding", "br")
+        });
+        assert_eq!(response.headers.get("Content-Encoding"), Some("br"));
+        assert_eq!(response.body.as_bytes().unwrap(), page().as_bytes());
+    }
+
+    #[test]
+    fn head_gets_the_headers_of_get() {
+        let router = Router::new()
+            .get("/style.css", |_, _| {
+                let len = page().len() as u64;
+                Response::new(StatusCode::Ok)
+                    .with_header("Content-Type", "text/css")
+                    .with_header("ETag", "\"abc-123\"")
+                    .with_body(Body::from_reader(io::Cursor::new(page()), len))
+            })
+            .get("/small", |_, _| Response::html("<p>hi</p>"));
+
+        let head_of = |method: Method, path: &str| {
+            let chain: [Box<dyn Middleware>; 1] = [Box::new(Compress::new())];
+            let endpoint = |request: &Request| router.handle(request);
+            let mut request = Request::new(method, path);
+            request.headers.set("Accept-Encoding", "gzip");
+
+            let mut written = Vec::new();
+            let mut response = Next::new(&chain, &endpoint).run(&mut request);
+            response.write_to(&mut written).unwrap();
+
+            // in any order, only which headers there are matters
+            let end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8(written[..end].to_vec()).unwrap();
+            let mut head: Vec<_> = head.split("\r\n").map(String::from).collect();
+            head.sort();
+            head.join("\n")
+        };
+
+        for path in ["/style.css", "/small"] {
+            assert_eq!(
+                head_of(Method::Head, path),
+                head_of(Method::Get, path),
+                "{path}"
+            );
+        }
+        assert!(head_of(Method::Head, "/style.css").contains("Content-Encoding: gzip"));
+        assert!(!head_of(Method::Head, "/small").contains("Content-Encoding"));
+    }
+
+    #[test]
+    fn existing_vary_is_extended() {
+        let response = run(&mut accepting("gzip"), || {
+            Response::html(page()).with_header("Vary", "Cookie")
+        });
+
+        assert!(response.headers.has_token("Vary", "Cookie"));
+        assert!(response.headers.has_token("Vary", "Accept-Encoding"));
+    }
+
+    #[test]
+    fn streamed_bodies_are_compressed_as_they_go() {
+        let response = run(&mut accepting("gzip"), || {
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "text/plain")
+                .with_body(Body::stream(|writer| {
+                    for line in 0..1000 {
+                        writeln!(writer, "line {line}")?;
+                        if line == 500 {
+                            writer.flush()?;
+                        }
+                    }
+                    Ok(())
+                }))
+        });
+
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        let body = response.body.into_bytes().unwrap();
+        let expected: String = (0..1000).map(|line| format!("line {line}\n")).collect();
+        assert_eq!(decode(&body, Encoding::Gzip), expected.as_bytes());
+    }
+
+    #[test]
+    fn file_bodies_lose_their_length_ranges_and_strong_etag() {
+        let text = page();
+        let len = text.len() as u64;
+
+        let response = run(&mut accepting("gzip"), || {
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "text/css")
+                .with_header("Content-Length", len.to_string())
+                .with_header("ETag", "\"abc-123\"")
+                .with_header("Accept-Ranges", "bytes")
+                .with_body(Body::from_reader(io::Cursor::new(page()), len))
+        });
+
+        assert!(!response.headers.contains("Content-Length"));
+        assert!(!response.headers.contains("Accept-Ranges"));
+        assert_eq!(response.headers.get("ETag"), Some("W/\"abc-123\""));
+        assert_eq!(response.body.len(), None);
+
+        let body = response.body.into_bytes().unwrap();
+        assert_eq!(decode(&body, Encoding::Gzip), text.as_bytes());
+    }
+}
@@ -0,0 +1,755 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    access_log::LogFormat,
+    connection::{KeepAlive, Limits},
+    log::Level,
+};
+
+pub const USAGE: &str = "\
+usage: web_server [options]
+
+options:
+  --config <path>              read settings from a config file first
+  --address <ip>               address to listen on (default 127.0.0.1)
+  --port <port>                port to listen on (default 7878)
+  --workers <count>            number of worker threads (default 4)
+  --max-workers <count>        worker threads the pool may grow to while it's busy
+                               (default the same as --workers)
+  --worker-idle-timeout <duration> how long an extra worker waits for work before
+                               stopping (default 60s)
+  --queue-size <count>         connections that may wait for a busy worker before
+                               new ones get 503 Service Unavailable (default 64)
+  --root <dir>                 document root for static files (default public)
+  --idle-timeout <duration>    how long an idle keep-alive connection stays open (default 5s)
+  --max-requests <count>       requests served per connection before closing it (default 100)
+  --shutdown-timeout <duration> how long in-flight requests get at shutdown (default 30s)
+  --request-timeout <duration> how long a client gets to send a whole request (default 10s)
+  --write-timeout <duration>   how long a single write of a response may block (default 30s)
+  --max-header-size <size>     largest request line and headers accepted (default 8k)
+  --max-body-size <size>       largest request body accepted (default 1m)
+  --log-level <level>          error, warn, info or debug (default info)
+  --access-log <path>          file to append the access log to, - for stderr (default -)
+  --log-format <format>        access log format, common or combined (default combined)
+  -h, --help                   print this message
+
+durations are seconds, or a number followed by ms, s or m, like 500ms
+sizes are bytes, or a number followed by k or m, like 64k
+flags override the config file, which overrides the defaults";
+
+// maps every command line flag to the config file key it overrides
+const FLAGS: [(&str, &str); 17] = [
+    ("--address", "address"),
+    ("--port", "port"),
+    ("--workers", "workers"),
+    ("--max-workers", "max_workers"),
+    ("--worker-idle-timeout", "timeouts.worker_idle"),
+    ("--queue-size", "queue_size"),
+    ("--root", "document_root"),
+    ("--idle-timeout", "timeouts.idle"),
+    ("--max-requests", "keep_alive.max_requests"),
+    ("--shutdown-timeout", "timeouts.shutdown"),
+    ("--request-timeout", "timeouts.request"),
+    ("--write-timeout", "timeouts.write"),
+    ("--max-header-size", "limits.max_header_size"),
+    ("--max-body-size", "limits.max_body_size"),
+    ("--log-level", "log.level"),
+    ("--access-log", "log.access"),
+    ("--log-format", "log.format"),
+];
+
+// the most worker threads we're willing to spawn, anything above is almost certainly a typo
+const MAX_WORKERS: usize = 1024;
+
+const MIN_HEADER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    pub workers: usize,
+    // `None` keeps the pool at `workers`
+    pub max_workers: Option<usize>,
+    pub worker_idle_timeout: Duration,
+    pub queue_size: usize,
+    pub document_root: PathBuf,
+    pub idle_timeout: Duration,
+    pub max_requests: usize,
+    pub shutdown_timeout: Duration,
+    pub request_timeout: Duration,
+    pub write_timeout: Duration,
+    pub max_header_size: usize,
+    pub max_body_size: u64,
+    pub log_level: Level,
+    // `None` writes the access log to stderr
+    pub access_log: Option<PathBuf>,
+    pub log_format: LogFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let keep_alive = KeepAlive::default();
+        let limits = Limits::default();
+
+        Self {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 7878,
+            workers: 4,
+            max_workers: None,
+            worker_idle_timeout: Duration::from_secs(60),
+            queue_size: 64,
+            document_root: PathBuf::from("public"),
+            idle_timeout: keep_alive.idle_timeout,
+            max_requests: keep_alive.max_requests,
+            shutdown_timeout: Duration::from_secs(30),
+            request_timeout: limits.request_timeout,
+            write_timeout: limits.write_timeout,
+            max_header_size: limits.max_head_size,
+            max_body_size: limits.max_body_size,
+            log_level: Level::Info,
+            access_log: None,
+            log_format: LogFormat::Combined,
+        }
+    }
+}
+
+impl Config {
+    // builds the config from command line arguments, like `env::args()`
+    // the first argument is the program name and is skipped
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Config, ConfigError> {
+        let mut config_path = None;
+        let mut overrides = Vec::new();
+
+        let mut args = args.skip(1);
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(ConfigError::Help);
+            }
+
+            // both `--port 8080` and `--port=8080` are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+
+            let value = match inline_value.or_else(|| args.next()) {
+                Some(value) => value,
+                None => return Err(ConfigError::MissingValue(flag)),
+            };
+
+            if flag == "--config" {
+                config_path = Some(PathBuf::from(value));
+                continue;
+            }
+
+            match FLAGS.iter().find(|(name, _)| *name == flag) {
+                Some((_, key)) => overrides.push((*key, value)),
+                None => return Err(ConfigError::UnknownFlag(flag)),
+            }
+        }
+
+        let mut config = Config::default();
+
+        if let Some(path) = config_path {
+            config.load_file(&path)?;
+        }
+
+        for (key, value) in overrides {
+            config.set(key, &value)?;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // reads `path` and applies every setting in it on top of the current values
+    pub fn load_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        self.apply_file(&contents)
+            .map_err(|(line, source)| ConfigError::AtLine {
+                path: path.to_path_buf(),
+                line,
+                source: Box::new(source),
+            })
+    }
+
+    // the config file is a small subset of TOML:
+    // ```
+    // # comments start with a hash
+    // port = 8080
+    // document_root = "public"
+    //
+    // [timeouts]
+    // idle = "2s"
+    // ```
+    // keys inside a `[section]` are looked up as `section.key`
+    // on error, returns the 1-based line number along with the error
+    fn apply_file(&mut self, contents: &str) -> Result<(), (usize, ConfigError)> {
+        let mut section = String::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let at_line = |err| (index + 1, err);
+
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| at_line(ConfigError::Syntax("unclosed section header")))?;
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at_line(ConfigError::Syntax("expected `key = value`")))?;
+
+            let key = key.trim();
+            let key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{section}.{key}")
+            };
+
+            let value = unquote(value.trim()).map_err(at_line)?;
+
+            self.set(&key, value).map_err(at_line)?;
+        }
+
+        Ok(())
+    }
+
+    // applies one setting, identified by its config file key
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "address" => self.address = parse(key, value, "not an ip address")?,
+            "port" => self.port = parse(key, value, "not a port number")?,
+            "workers" => self.workers = parse(key, value, "not a number")?,
+            "max_workers" => self.max_workers = Some(parse(key, value, "not a number")?),
+            "timeouts.worker_idle" => self.worker_idle_timeout = parse_duration(key, value)?,
+            "queue_size" => self.queue_size = parse(key, value, "not a number")?,
+            "document_root" => self.document_root = PathBuf::from(value),
+            "timeouts.idle" => self.idle_timeout = parse_duration(key, value)?,
+            "timeouts.shutdown" => self.shutdown_timeout = parse_duration(key, value)?,
+            "timeouts.request" => self.request_timeout = parse_duration(key, value)?,
+            "timeouts.write" => self.write_timeout = parse_duration(key, value)?,
+            "limits.max_header_size" => self.max_header_size = parse_size(key, value)? as usize,
+            "limits.max_body_size" => self.max_body_size = parse_size(key, value)?,
+            "keep_alive.max_requests" => self.max_requests = parse(key, value, "not a number")?,
+            "log.level" => self.log_level = parse(key, value, "not error, warn, info or debug")?,
+            "log.access" => {
+                self.access_log = match value {
+                    "-" => None,
+                    path => Some(PathBuf::from(path)),
+                }
+            }
+            "log.format" => self.log_format = parse(key, value, "not common or combined")?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+
+        Ok(())
+    }
+
+    // catches settings that parse fine but can't work, before the server starts
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, value: String, reason| {
+            Err(ConfigError::InvalidValue {
+                key: key.to_string(),
+                value,
+                reason,
+            })
+        };
+
+        if self.workers == 0 || self.workers > MAX_WORKERS {
+            return invalid(
+                "workers",
+                self.workers.to_string(),
+                "must be between 1 and 1024",
+            );
+        }
+
+        if let Some(max) = self.max_workers {
+            if max < self.workers || max > MAX_WORKERS {
+                return invalid(
+                    "max_workers",
+                    max.to_string(),
+                    "must be between workers and 1024",
+                );
+            }
+        }
+
+        if self.max_requests == 0 {
+            return invalid(
+                "keep_alive.max_requests",
+                self.max_requests.to_string(),
+                "must be at least 1",
+            );
+        }
+
+        for (key, timeout) in [
+            ("timeouts.idle", self.idle_timeout),
+            ("timeouts.worker_idle", self.worker_idle_timeout),
+            ("timeouts.request", self.request_timeout),
+            ("timeouts.write", self.write_timeout),
+        ] {
+            if timeout.is_zero() {
+                return invalid(key, String::from("0"), "must not be zero");
+            }
+        }
+
+        // anything smaller can't even hold a request line with a reasonable path
+        if self.max_header_size < MIN_HEADER_SIZE {
+            return invalid(
+                "limits.max_header_size",
+                self.max_header_size.to_string(),
+                "must be at least 1k",
+            );
+        }
+
+        if !self.document_root.is_dir() {
+            return invalid(
+                "document_root",
+                self.document_root.display().to_string(),
+                "is not a directory",
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    pub fn keep_alive(&self) -> KeepAlive {
+        KeepAlive {
+            idle_timeout: self.idle_timeout,
+            max_requests: self.max_requests,
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            request_timeout: self.request_timeout,
+            write_timeout: self.write_timeout,
+            max_head_size: self.max_header_size,
+            max_body_size: self.max_body_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // `--help` was passed, the caller should print `USAGE`
+    Help,
+    UnknownFlag(String),
+    MissingValue(String),
+    UnknownKey(String),
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: &'static str,
+    },
+    Syntax(&'static str),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // an error found on a specific line of a config file
+    AtLine {
+        path: PathBuf,
+        line: usize,
+        source: Box<ConfigError>,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{USAGE}"),
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown flag {flag}"),
+            ConfigError::MissingValue(flag) => write!(f, "{flag} needs a value"),
+            ConfigError::UnknownKey(key) => write!(f, "unknown setting {key:?}"),
+            ConfigError::InvalidValue { key, value, reason } => {
+                write!(f, "invalid {key} {value:?}: {reason}")
+            }
+            ConfigError::Syntax(message) => write!(f, "{message}"),
+            ConfigError::Io { path, source } => {
+                write!(f, "couldn't read {}: {source}", path.display())
+            }
+            ConfigError::AtLine { path, line, source } => {
+                write!(f, "{}:{line}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::AtLine { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(
+    key: &str,
+    value: &str,
+    reason: &'static str,
+) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason,
+    })
+}
+
+// `5` is five seconds, `500ms`, `5s` and `2m` carry their own unit
+pub fn parse_duration(key: &str, value: &str) -> Result<Duration, ConfigError> {
+    let invalid = || ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason: "not a duration like 5, 500ms, 5s or 2m",
+    };
+
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(invalid()),
+    }
+}
+
+// `512` is 512 bytes, `64k` and `2m` are kibibytes and mebibytes
+pub fn parse_size(key: &str, value: &str) -> Result<u64, ConfigError> {
+    let invalid = || ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason: "not a size like 512, 64k or 2m",
+    };
+
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+
+    let multiplier = match unit.to_ascii_lowercase().as_str() {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        _ => return Err(invalid()),
+    };
+
+    number.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+// drops a `#` comment, unless the `#` is inside a quoted string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn unquote(value: &str) -> Result<&str, ConfigError> {
+    match value.strip_prefix('"') {
+        Some(rest) => rest
+            .strip_suffix('"')
+            .ok_or(ConfigError::Syntax("unterminated string")),
+        None if value.is_empty() => Err(ConfigError::Syntax("missing value")),
+        None => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        let mut all = vec![
+            String::from("web_server"),
+            String::from("--root"),
+            env::temp_dir().display().to_string(),
+        ];
+        // the default document root doesn't exist where the tests run, so point it somewhere real
+        all.extend(list.iter().map(|arg| arg.to_string()));
+        all.into_iter()
+    }
+
+    #[test]
+    fn defaults_without_flags() {
+        let config = Config::from_args(args(&[])).unwrap();
+
+        assert_eq!(
+            config.socket_addr(),
+            "127.0.0.1:7878".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.keep_alive(), KeepAlive::default());
+        assert_eq!(config.limits(), Limits::default());
+    }
+
+    #[test]
+    fn limit_flags() {
+        let config = Config::from_args(args(&[
+            "--request-timeout",
+            "3s",
+            "--write-timeout=500ms",
+            "--max-header-size",
+            "16k",
+            "--max-body-size=10M",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            config.limits(),
+            Limits {
+                request_timeout: Duration::from_secs(3),
+                write_timeout: Duration::from_millis(500),
+                max_head_size: 16 * 1024,
+                max_body_size: 10 * 1024 * 1024,
+            }
+        );
+
+        assert!(matches!(
+            Config::from_args(args(&["--max-body-size", "1g"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "limits.max_body_size"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--max-header-size", "100"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "limits.max_header_size"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--request-timeout", "0"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "timeouts.request"
+        ));
+    }
+
+    #[test]
+    fn flags_in_both_forms() {
+        let config = Config::from_args(args(&[
+            "--address",
+            "0.0.0.0",
+            "--port=8080",
+            "--workers",
+            "16",
+            "--queue-size=0",
+            "--idle-timeout=250ms",
+            "--shutdown-timeout",
+            "1m",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            config.socket_addr(),
+            "0.0.0.0:8080".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(config.workers, 16);
+        assert_eq!(config.queue_size, 0);
+        assert_eq!(config.idle_timeout, Duration::from_millis(250));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn worker_flags() {
+        let config = Config::from_args(args(&[
+            "--workers",
+            "2",
+            "--max-workers=8",
+            "--worker-idle-timeout",
+            "10s",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.max_workers, Some(8));
+        assert_eq!(config.worker_idle_timeout, Duration::from_secs(10));
+
+        assert!(matches!(
+            Config::from_args(args(&["--workers", "4", "--max-workers", "2"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "max_workers"
+        ));
+        assert_eq!(Config::from_args(args(&[])).unwrap().max_workers, None);
+    }
+
+    #[test]
+    fn logging_flags() {
+        let config = Config::from_args(args(&[
+            "--log-level",
+            "DEBUG",
+            "--access-log",
+            "access.log",
+            "--log-format=common",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.log_level, Level::Debug);
+        assert_eq!(config.access_log, Some(PathBuf::from("access.log")));
+        assert_eq!(config.log_format, LogFormat::Common);
+
+        let config = Config::from_args(args(&["--access-log", "-"])).unwrap();
+        assert_eq!(config.access_log, None);
+
+        assert!(matches!(
+            Config::from_args(args(&["--log-level", "loud"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "log.level"
+        ));
+    }
+
+    #[test]
+    fn bad_flags() {
+        assert!(matches!(
+            Config::from_args(args(&["--verbose", "yes"])),
+            Err(ConfigError::UnknownFlag(flag)) if flag == "--verbose"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--port"])),
+            Err(ConfigError::MissingValue(flag)) if flag == "--port"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--port", "99999"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "port"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--help"])),
+            Err(ConfigError::Help)
+        ));
+    }
+
+    #[test]
+    fn validation_errors() {
+        assert!(matches!(
+            Config::from_args(args(&["--workers", "0"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "workers"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--max-requests", "0"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "keep_alive.max_requests"
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--root", "/definitely/not/here"])),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "document_root"
+        ));
+    }
+
+    #[test]
+    fn config_file_with_sections_and_comments() {
+        let mut config = Config::default();
+
+        config
+            .apply_file(
+                "\
+# a comment
+address = \"10.0.0.1\"   # trailing comment
+port = 9000
+
+[timeouts]
+idle = \"2s\"
+shutdown = 10
+
+[keep_alive]
+max_requests = 3
+",
+            )
+            .unwrap();
+
+        assert_eq!(
+            config.socket_addr(),
+            "10.0.0.1:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(config.idle_timeout, Duration::from_secs(2));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(10));
+        assert_eq!(config.max_requests, 3);
+    }
+
+    #[test]
+    fn config_file_errors_report_line() {
+        let mut config = Config::default();
+
+        let (line, err) = config
+            .apply_file("port = 1\n\n[timeouts]\nidel = 5\n")
+            .unwrap_err();
+        assert_eq!(line, 4);
+        assert!(matches!(err, ConfigError::UnknownKey(key) if key == "timeouts.idel"));
+
+        let (line, err) = config.apply_file("port 1\n").unwrap_err();
+        assert_eq!(line, 1);
+        assert!(matches!(err, ConfigError::Syntax(_)));
+
+        let (_, err) = config.apply_file("document_root = \"public\n").unwrap_err();
+        assert!(matches!(err, ConfigError::Syntax("unterminated string")));
+    }
+
+    #[test]
+    fn flags_override_config_file() {
+        let path = env::temp_dir().join(format!("web_server_config_{}.toml", std::process::id()));
+        fs::write(&path, "port = 9000\nworkers = 2\n").unwrap();
+
+        let config = Config::from_args(args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--workers",
+            "8",
+        ]));
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.workers, 8);
+    }
+
+    #[test]
+    fn missing_config_file() {
+        assert!(matches!(
+            Config::from_args(args(&["--config", "/definitely/not/here.toml"])),
+            Err(ConfigError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("t", "5").unwrap(), Duration::from_secs(5));
+        assert_eq!(
+            parse_duration("t", "750ms").unwrap(),
+            Duration::from_millis(750)
+        );
+        assert_eq!(parse_duration("t", "2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("t", "5h").is_err());
+        assert!(parse_duration("t", "ms").is_err());
+        assert!(parse_duration("t", "-1").is_err());
+    }
+}
@@ -0,0 +1,824 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::mpsc::{self, SyncSender},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    access_log::{AccessEntry, AccessLog},
+    log,
+    middleware::{Middleware, Next},
+    request::{Request, Version, MAX_BODY_SIZE, MAX_HEAD_SIZE},
+    response::{Response, StatusCode},
+    router::Router,
+};
+
+// how long an idle persistent connection is kept open, and how many requests it may serve
+// without a limit, a handful of clients could hold on to every worker forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    pub idle_timeout: Duration,
+    pub max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+// bounds on how long and how large a single request may be
+// the idle timeout only covers the wait for a request to start, so without a deadline for the rest,
+// a client trickling in one header byte at a time could keep a worker busy forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // from the first byte of a request until its body has been read completely
+    pub request_timeout: Duration,
+    // for each write of the response, so a client that stops reading can't block a worker
+    pub write_timeout: Duration,
+    pub max_head_size: usize,
+    pub max_body_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(30),
+            max_head_size: MAX_HEAD_SIZE,
+            max_body_size: MAX_BODY_SIZE,
+        }
+    }
+}
+
+// everything a worker needs to serve a connection
+// built once per server and shared between the workers through an Arc
+pub struct Service {
+    pub router: Router,
+    pub keep_alive: KeepAlive,
+    pub limits: Limits,
+    pub access_log: Option<AccessLog>,
+    // wrapped around the router, the first one outermost
+    pub middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Service {
+    pub fn new(router: Router) -> Self {
+        Self {
+            router,
+            keep_alive: KeepAlive::default(),
+            limits: Limits::default(),
+            access_log: None,
+            middleware: Vec::new(),
+        }
+    }
+
+    // runs `request` through the middleware and then the router
+    fn respond(&self, request: &mut Request) -> Response {
+        let router = |request: &Request| self.router.handle(request);
+        Next::new(&self.middleware, &router).run(request)
+    }
+
+    // writes `response` and records it in the access log
+    // the entry is recorded even if the client went away before the response was sent
+    fn send(
+        &self,
+        writer: &mut impl Write,
+        response: &mut Response,
+        request: Option<&Request>,
+        peer_addr: Option<SocketAddr>,
+        started: Instant,
+    ) -> io::Result<()> {
+        let written = match request {
+            Some(request) if request.version == Version::Http10 => {
+                response.write_close_delimited(writer)
+            }
+            _ => response.write_to(writer),
+        };
+
+        if let Some(access_log) = &self.access_log {
+            access_log.record(&AccessEntry {
+                peer_addr,
+                request,
+                status: response.status,
+                bytes: written.as_ref().copied().unwrap_or(0),
+                latency: started.elapsed(),
+                time: SystemTime::now(),
+            });
+        }
+
+        written.map(|_| ())
+    }
+}
+
+// serves requests off `stream` until the client closes it, asks to close it,
+// stays idle for longer than the idle timeout, or reaches the request limit
+// pipelined requests just sit in the `BufReader` until the previous response has been written
+pub fn handle_connection(stream: TcpStream, service: &Service) -> io::Result<()> {
+    let keep_alive = &service.keep_alive;
+    let limits = &service.limits;
+    let peer_addr = stream.peer_addr().ok();
+
+    stream.set_write_timeout(Some(limits.write_timeout))?;
+
+    let mut reader = BufReader::new(DeadlineReader::new(&stream));
+    let mut writer = &stream;
+    // `&TcpStream` implements both `Read` and `Write`,
+    // so the same socket can be read through the buffer and written to directly
+
+    for served in 1..=keep_alive.max_requests {
+        reader.get_mut().deadline = Instant::now() + keep_alive.idle_timeout;
+
+        if !wait_for_request(&mut reader)? {
+            // the client closed the connection, or went quiet, between requests
+            break;
+        }
+
+        let started = Instant::now();
+        // latency is measured from the first byte of the request to the last byte of the response
+
+        reader.get_mut().deadline = started + limits.request_timeout;
+
+        let parsed =
+            Request::parse_limited(&mut reader, limits.max_head_size, limits.max_body_size);
+
+        let mut request = match parsed {
+            Ok(request) => request,
+            Err(err) => {
+                // after a malformed request we can't tell where the next one starts, so close
+                log::debug(format_args!("Bad request from {peer_addr:?}: {err}"));
+
+                let mut response = Response::new(err.status()).with_header("Connection", "close");
+                service.send(&mut writer, &mut response, None, peer_addr, started)?;
+                linger_close(&stream, &mut reader, LINGER_TIME);
+                break;
+            }
+        };
+
+        request.peer_addr = peer_addr;
+
+        let mut response = service.respond(&mut request);
+
+        // HTTP/1.0 clients don't understand chunked encoding, so a streamed body is sent as it is,
+        // and closing the connection is what tells them it has ended
+        let close_delimited = request.version == Version::Http10 && response.body.len().is_none();
+
+        let persist = wants_keep_alive(&request)
+            && served < keep_alive.max_requests
+            && !close_delimited
+            && response.upgrade.is_none()
+            && !response.headers.has_token("Connection", "close");
+
+        if response.status == StatusCode::SwitchingProtocols {
+            // the `Connection: Upgrade` it came with has to stay as it is
+        } else if !persist {
+            response.headers.set("Connection", "close");
+        } else if request.version == Version::Http10 {
+            // HTTP/1.0 clients only keep the connection open if told so explicitly
+            response.headers.set("Connection", "keep-alive");
+        }
+
+        service.send(
+            &mut writer,
+            &mut response,
+            Some(&request),
+            peer_addr,
+            started,
+        )?;
+
+        if let Some(upgrade) = response.upgrade.take() {
+            // the connection belongs to the upgrade from here on, along with anything
+            // the client already sent for it, and it keeps the worker until it returns
+            let buffered = reader.buffer().to_vec();
+            drop(reader);
+
+            stream.set_read_timeout(None)?;
+            upgrade.run(stream, buffered);
+            return Ok(());
+        }
+
+        if !persist {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// answers a connection the server has no capacity for with 503 and closes it, without reading the request
+// runs on the accepting thread, but the response is small enough to fit the empty send buffer of a new socket,
+// and the lingering part of the close is left to `closer`
+pub fn reject_connection(stream: TcpStream, service: &Service, closer: &Closer) -> io::Result<()> {
+    let started = Instant::now();
+    let peer_addr = stream.peer_addr().ok();
+
+    stream.set_write_timeout(Some(service.limits.write_timeout))?;
+
+    let mut response = Response::new(StatusCode::ServiceUnavailable)
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    service.send(&mut &stream, &mut response, None, peer_addr, started)?;
+
+    // the client sees the end of the response right away, however long the closer takes to get to it
+    stream.shutdown(Shutdown::Write)?;
+    closer.close(stream);
+
+    Ok(())
+}
+
+// discards what rejected clients still send on a thread of its own, one connection after the other,
+// so a client that keeps its connection open can't slow down accepting
+// the thread ends once the `Closer` is dropped and the connections it was given are closed
+pub struct Closer {
+    sender: SyncSender<TcpStream>,
+}
+
+impl Closer {
+    pub fn spawn() -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(CLOSER_BACKLOG);
+
+        let started = thread::Builder::new()
+            .name(String::from("closer"))
+            .spawn(move || {
+                for stream in receiver {
+                    let mut reader = BufReader::new(DeadlineReader::new(&stream));
+                    drain(&mut reader, REJECT_LINGER_TIME);
+                }
+            });
+
+        // without the thread every send fails, and connections are closed without lingering
+        if let Err(err) = started {
+            log::error(format_args!("Couldn't start the closer thread: {err}"));
+        }
+
+        Self { sender }
+    }
+
+    // if the closer is too far behind, the connection is closed right away instead,
+    // which risks a reset but can't hold anything up
+    fn close(&self, stream: TcpStream) {
+        let _ = self.sender.try_send(stream);
+    }
+}
+
+// blocks until the first byte of the next request arrives
+// returns false if the connection was closed or the idle timeout passed first
+fn wait_for_request(reader: &mut impl BufRead) -> io::Result<bool> {
+    match reader.fill_buf() {
+        Ok(buffer) => Ok(!buffer.is_empty()),
+        Err(err) if is_timeout(&err) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// a read timeout shows up as `WouldBlock` on unix and `TimedOut` on windows
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// reads from the socket until a fixed point in time, rather than for a fixed time per read
+// a plain read timeout restarts with every byte that arrives, so it can't stop a slow client
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl<'a> DeadlineReader<'a> {
+    fn new(stream: &'a TcpStream) -> Self {
+        Self {
+            stream,
+            deadline: Instant::now(),
+        }
+    }
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+
+        // a zero read timeout means no timeout at all, so check for an expired deadline here
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+// how long, and how much, to keep reading after an error response before closing
+const LINGER_TIME: Duration = Duration::from_secs(1);
+const LINGER_BYTES: u64 = 64 * 1024;
+
+// rejected connections share one closer thread, so it can't afford to wait as long on each
+const REJECT_LINGER_TIME: Duration = Duration::from_millis(100);
+
+// how many rejected connections can wait for the closer before they're closed without lingering
+const CLOSER_BACKLOG: usize = 64;
+
+// closing a socket with unread data in it makes the OS reset the connection,
+// and the reset can reach the client before it has read our error response
+// so stop sending, then discard whatever the client still sends, for a bounded time
+fn linger_close(stream: &TcpStream, reader: &mut BufReader<DeadlineReader>, linger: Duration) {
+    if stream.shutdown(Shutdown::Write).is_err() {
+        return;
+    }
+
+    drain(reader, linger);
+}
+
+// the second half of `linger_close`, for a stream that has already stopped sending
+fn drain(reader: &mut BufReader<DeadlineReader>, linger: Duration) {
+    reader.get_mut().deadline = Instant::now() + linger;
+    let _ = io::copy(&mut reader.take(LINGER_BYTES), &mut io::sink());
+}
+
+// HTTP/1.1 connections are persistent unless the client says `Connection: close`
+// HTTP/1.0 connections close after one request unless the client says `Connection: keep-alive`
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.has_token("Connection", "close"),
+        Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        response::Body,
+        websocket::{
+            frame::{
+                tests::{client_frame, server_frame},
+                Opcode,
+            },
+            WebSocket,
+        },
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+        time::Instant,
+    };
+
+    // starts a server on an ephemeral port that handles a single connection
+    fn serve_one(keep_alive: KeepAlive) -> (TcpStream, thread::JoinHandle<io::Result<()>>) {
+        serve_limited(keep_alive, Limits::default())
+    }
+
+    fn serve_limited(
+        keep_alive: KeepAlive,
+        limits: Limits,
+    ) -> (TcpStream, thread::JoinHandle<io::Result<()>>) {
+        serve_with(keep_alive, limits, Vec::new())
+    }
+
+    fn serve_with(
+        keep_alive: KeepAlive,
+        limits: Limits,
+        middleware: Vec<Box<dyn Middleware>>,
+    ) -> (TcpStream, thread::JoinHandle<io::Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/endless", |_, _| {
+                    Response::text("").with_body(Body::stream(|writer| loop {
+                        writer.write_all(&[b'x'; 64 * 1024])?;
+                    }))
+                })
+                .get("/panic", |_, _| panic!("handler failed"))
+                .websocket("/ws", |mut socket: WebSocket| {
+                    let sender = socket.sender();
+                    for message in socket.messages() {
+                        sender.send(message).unwrap();
+                    }
+                })
+                .get("/:name", |_, params| {
+                    Response::text(params.get("name").unwrap().to_string())
+                })
+                .get("/stream/:name", |_, params| {
+                    let name = params.get("name").unwrap().to_string();
+                    Response::text("").with_body(Body::stream(move |writer| {
+                        writer.write_all(name.as_bytes())
+                    }))
+                })
+                .post("/upload", |request, _| {
+                    Response::text(request.body.len().to_string())
+                });
+
+            let service = Service {
+                keep_alive,
+                limits,
+                middleware,
+                ..Service::new(router)
+            };
+
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &service)
+        });
+
+        let client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        (client, server)
+    }
+
+    // reads one response, returning its head and body
+    fn read_response(reader: &mut impl BufRead) -> (String, String) {
+        let mut head = String::new();
+
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+            head.push_str(&line);
+        }
+
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    fn assert_closed(reader: &mut impl Read) {
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty(), "connection should have been closed");
+    }
+
+    #[test]
+    fn websocket_takes_over_the_connection() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        // the first message comes right behind the handshake, before the answer
+        let mut handshake = b"GET /ws HTTP/1.1\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            \r\n"
+            .to_vec();
+        handshake.extend_from_slice(&client_frame(true, Opcode::Text, b"first"));
+        client.write_all(&handshake).unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Connection: Upgrade\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        assert_eq!(server_frame(&mut reader), (Opcode::Text, b"first".to_vec()));
+
+        (&client)
+            .write_all(&client_frame(true, Opcode::Binary, &[1, 2]))
+            .unwrap();
+        assert_eq!(server_frame(&mut reader), (Opcode::Binary, vec![1, 2]));
+
+        (&client)
+            .write_all(&client_frame(true, Opcode::Close, &1000u16.to_be_bytes()))
+            .unwrap();
+        assert_eq!(server_frame(&mut reader).0, Opcode::Close);
+
+        assert_closed(&mut reader);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn pipelined_requests_on_one_connection() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        client
+            .write_all(b"GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\n\r\nGET /three HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut reader = BufReader::new(&client);
+
+        for expected in ["one", "two"] {
+            let (head, body) = read_response(&mut reader);
+            assert!(head.starts_with("HTTP/1.1 200 OK"));
+            assert!(!head.contains("Connection: close"));
+            assert_eq!(body, expected);
+        }
+
+        let (head, body) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"));
+        assert_eq!(body, "three");
+
+        assert_closed(&mut reader);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn http_1_0_closes_by_default() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        client.write_all(b"GET /a HTTP/1.0\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"));
+
+        assert_closed(&mut reader);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn streamed_response_is_chunked() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        client
+            .write_all(b"GET /stream/abc HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n3\r\nabc\r\n0\r\n\r\n"));
+
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn streamed_response_ends_with_the_connection_for_http_1_0() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        // even when asked to keep the connection open, it's the only way to mark the end
+        client
+            .write_all(b"GET /stream/abc HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\nabc"));
+
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn endless_stream_for_http_1_0_is_sent_not_collected() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        client.write_all(b"GET /endless HTTP/1.0\r\n\r\n").unwrap();
+
+        // collecting it first would never send a byte
+        let mut start = vec![0; 256 * 1024];
+        client.read_exact(&mut start).unwrap();
+        assert!(start.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(start.ends_with(b"xxxx"));
+
+        drop(client);
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn http_1_0_keep_alive_on_request() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        client
+            .write_all(b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.contains("Connection: keep-alive"));
+
+        (&client).write_all(b"GET /b HTTP/1.0\r\n\r\n").unwrap();
+        let (head, body) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"));
+        assert_eq!(body, "b");
+
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn max_requests_per_connection() {
+        let (mut client, server) = serve_one(KeepAlive {
+            max_requests: 2,
+            ..KeepAlive::default()
+        });
+
+        client
+            .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(!head.contains("Connection: close"));
+
+        let (head, body) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"));
+        assert_eq!(body, "b");
+
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn idle_connection_times_out() {
+        let (mut client, server) = serve_one(KeepAlive {
+            idle_timeout: Duration::from_millis(100),
+            ..KeepAlive::default()
+        });
+
+        client.write_all(b"GET /a HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(&client);
+        read_response(&mut reader);
+
+        let start = Instant::now();
+        assert_closed(&mut reader);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn middleware_wraps_every_request() {
+        let middleware: Vec<Box<dyn Middleware>> = vec![
+            Box::new(crate::middleware::RequestId::new()),
+            Box::new(crate::middleware::CatchPanic),
+        ];
+        let (mut client, server) = serve_with(KeepAlive::default(), Limits::default(), middleware);
+
+        client
+            .write_all(b"GET /fine HTTP/1.1\r\n\r\nGET /panic HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let mut reader = BufReader::new(&client);
+
+        let (head, body) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("X-Request-Id: "));
+        assert_eq!(body, "fine");
+
+        // the panic is answered instead of just dropping the connection
+        let (head, _) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 500 Internal Server Error"));
+        assert!(head.contains("X-Request-Id: "));
+        assert!(head.contains("Connection: close"));
+
+        assert_closed(&mut reader);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn malformed_request_closes_connection() {
+        let (mut client, server) = serve_one(KeepAlive::default());
+
+        client.write_all(b"NONSENSE\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(head.contains("Connection: close"));
+
+        server.join().unwrap().unwrap();
+    }
+
+    fn short_request_timeout() -> Limits {
+        Limits {
+            request_timeout: Duration::from_millis(300),
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn slow_headers_time_out() {
+        let (mut client, server) = serve_limited(KeepAlive::default(), short_request_timeout());
+
+        client.write_all(b"GET /a HTTP/1.1\r\n").unwrap();
+
+        // every byte arrives well within the idle timeout, but the request as a whole never finishes
+        let start = Instant::now();
+        for byte in b"X-Slow: ".iter().cycle().take(40) {
+            if client.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(25));
+        }
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 408 Request Timeout"));
+        assert!(head.contains("Connection: close"));
+
+        server.join().unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn slow_body_times_out() {
+        let (mut client, server) = serve_limited(KeepAlive::default(), short_request_timeout());
+
+        client
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 10\r\n\r\nab")
+            .unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 408 Request Timeout"));
+
+        assert_closed(&mut reader);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn oversized_headers_are_refused() {
+        let limits = Limits {
+            max_head_size: 64,
+            ..Limits::default()
+        };
+        let (mut client, server) = serve_limited(KeepAlive::default(), limits);
+
+        let request = format!("GET /a HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(100));
+        client.write_all(request.as_bytes()).unwrap();
+
+        let mut reader = BufReader::new(&client);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+
+        assert_closed(&mut reader);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn oversized_bodies_are_refused() {
+        let limits = Limits {
+            max_body_size: 4,
+            ..Limits::default()
+        };
+
+        for request in [
+            "POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+            "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+        ] {
+            let (mut client, server) = serve_limited(KeepAlive::default(), limits);
+            client.write_all(request.as_bytes()).unwrap();
+
+            // the unread body is drained before closing, so the client gets the response and not a reset
+            let mut reader = BufReader::new(&client);
+            let (head, _) = read_response(&mut reader);
+            assert!(head.starts_with("HTTP/1.1 413 Payload Too Large"));
+
+            assert_closed(&mut reader);
+            server.join().unwrap().unwrap();
+        }
+
+        let (mut client, server) = serve_limited(KeepAlive::default(), limits);
+        client
+            .write_all(
+                b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\nConnection: close\r\n\r\nhell",
+            )
+            .unwrap();
+
+        let (head, body) = read_response(&mut BufReader::new(&client));
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(body, "4");
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn client_that_stops_reading_times_out() {
+        let limits = Limits {
+            write_timeout: Duration::from_millis(200),
+            ..Limits::default()
+        };
+        let (mut client, server) = serve_limited(KeepAlive::default(), limits);
+
+        // ask for an endless response and never read it
+        client.write_all(b"GET /endless HTTP/1.1\r\n\r\n").unwrap();
+
+        let err = server.join().unwrap().unwrap_err();
+        assert!(is_timeout(&err), "unexpected error {err:?}");
+    }
+}
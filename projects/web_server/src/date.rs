@@ -0,0 +1,310 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+// a point in time broken down into UTC calendar fields
+// std has no calendar support, so this is the small part of one the server needs for logs and headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcDateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl UtcDateTime {
+    pub fn now() -> Self {
+        Self::from_system_time(SystemTime::now())
+    }
+
+    // times before 1970 are clamped to the epoch, the server never deals with those
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+
+        Self::from_unix_seconds(seconds)
+    }
+
+    pub fn from_unix_seconds(seconds: u64) -> Self {
+        let days = (seconds / 86_400) as i64;
+        let rest = seconds % 86_400;
+
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (rest / 3600) as u32,
+            minute: (rest % 3600 / 60) as u32,
+            second: (rest % 60) as u32,
+        }
+    }
+
+    // parses a date header like `Last-Modified` or `If-Modified-Since`
+    // HTTP/1.1 sends `Sun, 06 Nov 1994 08:49:37 GMT`, but older clients may still send
+    // `Sunday, 06-Nov-94 08:49:37 GMT` or asctime's `Sun Nov  6 08:49:37 1994`, so those are read too
+    // the weekday is ignored, it follows from the date anyway
+    pub fn parse_http_date(input: &str) -> Option<Self> {
+        let parts: Vec<_> = input.split_whitespace().collect();
+
+        let (day, month, year, time) = match parts.as_slice() {
+            [_, day, month, year, time, "GMT"] => (*day, *month, parse_year(year)?, *time),
+            [_, date, time, "GMT"] => {
+                let [day, month, year] = date.split('-').collect::<Vec<_>>()[..] else {
+                    return None;
+                };
+                if year.len() != 2 {
+                    return None;
+                }
+                // two digit years are read as the closest one that isn't far in the future
+                let year: i64 = parse_digits(year)?.into();
+                let year = if year < 70 { 2000 + year } else { 1900 + year };
+                (day, month, year, *time)
+            }
+            [_, month, day, time, year] => (*day, *month, parse_year(year)?, *time),
+            _ => return None,
+        };
+
+        let [hour, minute, second] = time.split(':').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+
+        let time = Self {
+            year,
+            month: MONTHS.iter().position(|name| *name == month)? as u32 + 1,
+            day: parse_digits(day).filter(|day| (1..=31).contains(day))?,
+            hour: parse_digits(hour).filter(|hour| *hour < 24)?,
+            minute: parse_digits(minute).filter(|minute| *minute < 60)?,
+            // 60 for a leap second
+            second: parse_digits(second).filter(|second| *second <= 60)?,
+        };
+
+        Some(time)
+    }
+
+    // times before 1970 are clamped to the epoch, like in `from_system_time`
+    pub fn to_unix_seconds(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let seconds = days * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+
+        seconds.max(0) as u64
+    }
+
+    // `Sun, 06 Nov 1994 08:49:37 GMT`, used by HTTP headers like `Last-Modified`
+    pub fn http_date(&self) -> impl fmt::Display + '_ {
+        Formatted(self, |time, f| {
+            // 1970-01-01 was a Thursday
+            let weekday = (days_from_civil(time.year, time.month, time.day) + 4).rem_euclid(7);
+
+            write!(
+                f,
+                "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+                WEEKDAYS[weekday as usize],
+                time.day,
+                MONTHS[time.month as usize - 1],
+                time.year,
+                time.hour,
+                time.minute,
+                time.second
+            )
+        })
+    }
+
+    // `2000-10-10T13:55:36Z`, used by the diagnostic log
+    pub fn iso8601(&self) -> impl fmt::Display + '_ {
+        Formatted(self, |time, f| {
+            write!(
+                f,
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                time.year, time.month, time.day, time.hour, time.minute, time.second
+            )
+        })
+    }
+
+    // `10/Oct/2000:13:55:36 +0000`, used by the access log
+    pub fn common_log(&self) -> impl fmt::Display + '_ {
+        Formatted(self, |time, f| {
+            write!(
+                f,
+                "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+                time.day,
+                MONTHS[time.month as usize - 1],
+                time.year,
+                time.hour,
+                time.minute,
+                time.second
+            )
+        })
+    }
+}
+
+// lets each format be a closure instead of a separate wrapper type
+struct Formatted<'a>(
+    &'a UtcDateTime,
+    fn(&UtcDateTime, &mut fmt::Formatter<'_>) -> fmt::Result,
+);
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.1)(self.0, f)
+    }
+}
+
+// converts days since 1970-01-01 into a (year, month, day) date
+// this is Howard Hinnant's `civil_from_days` algorithm, which counts in 400 year eras
+// starting in March, so the leap day falls at the very end of each year
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // 0 is March
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+// the inverse of `civil_from_days`, from the same algorithm
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from((month + 9) % 12); // 0 is March
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+// one or two digits, date fields never have a sign or more
+fn parse_digits(digits: &str) -> Option<u32> {
+    if digits.is_empty() || digits.len() > 2 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+fn parse_year(digits: &str) -> Option<i64> {
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch() {
+        let time = UtcDateTime::from_unix_seconds(0);
+
+        assert_eq!(time.iso8601().to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(time.common_log().to_string(), "01/Jan/1970:00:00:00 +0000");
+    }
+
+    #[test]
+    fn known_dates() {
+        // the example date from the apache docs
+        let time = UtcDateTime::from_unix_seconds(971_186_136);
+        assert_eq!(time.common_log().to_string(), "10/Oct/2000:13:55:36 +0000");
+
+        // a leap day
+        let time = UtcDateTime::from_unix_seconds(1_709_164_800);
+        assert_eq!(time.iso8601().to_string(), "2024-02-29T00:00:00Z");
+
+        // the day after the end of a century that isn't a leap year
+        let time = UtcDateTime::from_unix_seconds(4_107_542_400);
+        assert_eq!(time.iso8601().to_string(), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn http_dates() {
+        // the example date from the HTTP spec
+        let time = UtcDateTime::from_unix_seconds(784_111_777);
+        assert_eq!(
+            time.http_date().to_string(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+
+        let time = UtcDateTime::from_unix_seconds(1_709_164_800);
+        assert_eq!(
+            time.http_date().to_string(),
+            "Thu, 29 Feb 2024 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parses_every_http_date_format() {
+        for input in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            let time = UtcDateTime::parse_http_date(input);
+            assert_eq!(
+                time.map(|time| time.to_unix_seconds()),
+                Some(784_111_777),
+                "{input:?}"
+            );
+        }
+
+        // two digit years before 70 are in this century
+        let time = UtcDateTime::parse_http_date("Friday, 01-Jan-21 00:00:00 GMT").unwrap();
+        assert_eq!(time.year, 2021);
+    }
+
+    #[test]
+    fn round_trips_through_unix_seconds() {
+        for seconds in [0, 951_782_400, 1_709_164_800, 4_107_542_400] {
+            let time = UtcDateTime::from_unix_seconds(seconds);
+            assert_eq!(time.to_unix_seconds(), seconds);
+
+            let parsed = UtcDateTime::parse_http_date(&time.http_date().to_string());
+            assert_eq!(parsed, Some(time));
+        }
+    }
+
+    #[test]
+    fn rejects_broken_dates() {
+        for input in [
+            "",
+            "yesterday",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, +6 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+        ] {
+            assert_eq!(UtcDateTime::parse_http_date(input), None, "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,764 @@
+use std::io::{self, Write};
+
+// DEFLATE (RFC 1951) turns data into a series of blocks, each one of:
+// - stored, the bytes as they are, for data that doesn't get any smaller
+// - compressed with the fixed Huffman codes from the spec, which every decoder knows already
+// - compressed with Huffman codes of its own, sent at the start of the block
+// this encoder only makes the first two, which gets most of the way for text,
+// repeated strings are replaced by a (length, distance) back reference to an earlier copy
+
+// how far back a reference can reach, and so how much history has to be kept
+const WINDOW_SIZE: usize = 32 * 1024;
+// input is compressed this much at a time, each piece becoming one block
+// small enough that a block can always be stored instead, a stored block's length is a u16
+const BLOCK_SIZE: usize = 32 * 1024;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// earlier positions are found by a hash of the 3 bytes starting there
+const HASH_BITS: u32 = 14;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+// how many earlier positions with the same hash are tried, more finds longer matches but takes longer
+const MAX_CHAIN: usize = 64;
+const NO_POSITION: u32 = u32::MAX;
+
+// the length codes 257 to 285 stand for a base length plus that many extra bits
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// and the distance codes 0 to 29 the same for distances
+pub(crate) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const END_OF_BLOCK: u16 = 256;
+
+// compresses everything written to it into `writer` as raw DEFLATE data
+// `flush` ends the current block so everything written so far can be decoded,
+// and `finish` has to be called at the end to write the last block
+pub struct Deflater<W: Write> {
+    writer: BitWriter<W>,
+    // up to `WINDOW_SIZE` bytes already compressed, then the input that hasn't been yet
+    data: Vec<u8>,
+    // where the input that hasn't been compressed starts in `data`
+    start: usize,
+    // the most recent position for each hash, and the one before each position with the same hash,
+    // kept here so they're allocated once rather than for every block
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+// a piece of compressed input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    // a copy of `len` bytes from `distance` bytes back
+    Match { len: u16, distance: u16 },
+}
+
+impl Token {
+    // bits it takes with the fixed codes
+    fn fixed_bits(self) -> usize {
+        match self {
+            Token::Literal(byte) => fixed_literal(byte.into()).1 as usize,
+            Token::Match { len, distance } => {
+                let (code, _) = length_code(len);
+                let (distance_code, _) = distance_code(distance);
+
+                fixed_literal(257 + code as u16).1 as usize
+                    + LENGTH_EXTRA[code] as usize
+                    + 5
+                    + DISTANCE_EXTRA[distance_code] as usize
+            }
+        }
+    }
+}
+
+impl<W: Write> Deflater<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: BitWriter::new(writer),
+            data: Vec::with_capacity(WINDOW_SIZE + BLOCK_SIZE),
+            start: 0,
+            head: Vec::new(),
+            prev: Vec::new(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer.inner
+    }
+
+    // compresses whatever is left as the final block, and hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.block(self.data.len(), true)?;
+        self.writer.align();
+        self.writer.flush_out()?;
+
+        Ok(self.writer.inner)
+    }
+
+    fn pending(&self) -> usize {
+        self.data.len() - self.start
+    }
+
+    // compresses `data[start..end]` as one block, stored if that turns out smaller
+    fn block(&mut self, end: usize, last: bool) -> io::Result<()> {
+        let tokens = self.tokens(end);
+
+        let fixed_bits = 3 + tokens.iter().map(|token| token.fixed_bits()).sum::<usize>() + 7;
+        // the header, up to 7 bits to get to a whole byte, the length twice, then the bytes
+        let stored_bits = 3 + 7 + 32 + (end - self.start) * 8;
+
+        if fixed_bits <= stored_bits {
+            self.writer.fixed_block(&tokens, last);
+        } else {
+            self.writer.stored_block(&self.data[self.start..end], last);
+        }
+
+        self.start = end;
+
+        // only the last `WINDOW_SIZE` bytes can still be referred to
+        if self.start > WINDOW_SIZE {
+            let old = self.start - WINDOW_SIZE;
+            self.data.drain(..old);
+            self.start -= old;
+        }
+
+        self.writer.flush_out()
+    }
+
+    // finds the matches in `data[start..end]`, looking back into the history too
+    fn tokens(&mut self, end: usize) -> Vec<Token> {
+        let data = &self.data[..end];
+        let (head, prev) = (&mut self.head, &mut self.prev);
+
+        // positions are only good for this block, the history is shifted between them
+        head.clear();
+        head.resize(HASH_SIZE, NO_POSITION);
+        prev.clear();
+        prev.resize(end, NO_POSITION);
+
+        for pos in 0..self.start {
+            insert(data, head, prev, pos);
+        }
+
+        let mut tokens = Vec::with_capacity(end - self.start);
+        let mut pos = self.start;
+
+        while pos < end {
+            let (len, distance) = longest_match(data, head, prev, pos);
+
+            if len >= MIN_MATCH {
+                tokens.push(Token::Match {
+                    len: len as u16,
+                    distance: distance as u16,
+                });
+
+                for pos in pos..pos + len {
+                    insert(data, head, prev, pos);
+                }
+                pos += len;
+            } else {
+                tokens.push(Token::Literal(data[pos]));
+                insert(data, head, prev, pos);
+                pos += 1;
+            }
+        }
+
+        tokens
+    }
+}
+
+impl<W: Write> Write for Deflater<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // taking no more than a block at a time keeps `data` from growing,
+        // `write_all` comes back with the rest
+        let taken = buf.len().min(BLOCK_SIZE - self.pending());
+        self.data.extend_from_slice(&buf[..taken]);
+
+        if self.pending() == BLOCK_SIZE {
+            self.block(self.data.len(), false)?;
+        }
+
+        Ok(taken)
+    }
+
+    // ends the current block, then adds an empty stored block,
+    // which pads the output to a whole byte so the decoder can get at everything written so far
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending() > 0 {
+            self.block(self.data.len(), false)?;
+            self.writer.stored_block(&[], false);
+            self.writer.flush_out()?;
+        }
+
+        self.writer.inner.flush()
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let bytes = (usize::from(data[pos]) << 10)
+        ^ (usize::from(data[pos + 1]) << 5)
+        ^ usize::from(data[pos + 2]);
+    bytes & (HASH_SIZE - 1)
+}
+
+// makes `pos` the first place to look for the 3 bytes starting there
+fn insert(data: &[u8], head: &mut [u32], prev: &mut [u32], pos: usize) {
+    if pos + MIN_MATCH > data.len() {
+        return;
+    }
+
+    let hash = hash(data, pos);
+    prev[pos] = head[hash];
+    head[hash] = pos as u32;
+}
+
+// the longest earlier copy of the bytes at `pos`, as its length and how far back it is
+fn longest_match(data: &[u8], head: &[u32], prev: &[u32], pos: usize) -> (usize, usize) {
+    let max_len = (data.len() - pos).min(MAX_MATCH);
+    if max_len < MIN_MATCH {
+        return (0, 0);
+    }
+
+    let wanted = &data[pos..pos + max_len];
+    let mut best = (0, 0);
+    let mut candidate = head[hash(data, pos)];
+
+    for _ in 0..MAX_CHAIN {
+        if candidate == NO_POSITION {
+            break;
+        }
+
+        let earlier = candidate as usize;
+        let distance = pos - earlier;
+        // the chain goes further back with every step, so nothing after this is in reach either
+        if distance > WINDOW_SIZE {
+            break;
+        }
+
+        // only worth comparing the whole thing if it could beat the best so far
+        if data[earlier + best.0] == wanted[best.0.min(max_len - 1)] {
+            let len = data[earlier..]
+                .iter()
+                .zip(wanted)
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            if len > best.0 {
+                best = (len, distance);
+                if len == max_len {
+                    break;
+                }
+            }
+        }
+
+        candidate = prev[earlier];
+    }
+
+    best
+}
+
+// the index into `LENGTH_BASE` for a match length, and the extra bits that go with it
+fn length_code(len: u16) -> (usize, u16) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base <= len)
+        .unwrap_or(0);
+    (code, len - LENGTH_BASE[code])
+}
+
+fn distance_code(distance: u16) -> (usize, u16) {
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base <= distance)
+        .unwrap_or(0);
+    (code, distance - DISTANCE_BASE[code])
+}
+
+// the fixed Huffman code for a literal or length symbol, and how many bits it is
+fn fixed_literal(symbol: u16) -> (u32, u32) {
+    let symbol = u32::from(symbol);
+
+    match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    }
+}
+
+// collects bits into bytes, the first bit in the lowest bit of a byte, the way DEFLATE wants them
+struct BitWriter<W> {
+    inner: W,
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            out: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    // plain values go lowest bit first
+    fn bits(&mut self, value: u32, count: u32) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go highest bit first, so they're reversed
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    // pads with zeros to the next whole byte
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.bits(0, 8 - self.count);
+        }
+    }
+
+    fn stored_block(&mut self, bytes: &[u8], last: bool) {
+        self.bits(u32::from(last), 1);
+        self.bits(0b00, 2);
+        self.align();
+
+        let len = bytes.len() as u16;
+        self.out.extend_from_slice(&len.to_le_bytes());
+        self.out.extend_from_slice(&(!len).to_le_bytes());
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn fixed_block(&mut self, tokens: &[Token], last: bool) {
+        self.bits(u32::from(last), 1);
+        self.bits(0b01, 2);
+
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => {
+                    let (code, len) = fixed_literal(byte.into());
+                    self.code(code, len);
+                }
+                Token::Match { len, distance } => {
+                    let (length_code, extra) = length_code(len);
+                    let (code, bits) = fixed_literal(257 + length_code as u16);
+                    self.code(code, bits);
+                    self.bits(extra.into(), LENGTH_EXTRA[length_code].into());
+
+                    let (distance_code, extra) = distance_code(distance);
+                    self.code(distance_code as u32, 5);
+                    self.bits(extra.into(), DISTANCE_EXTRA[distance_code].into());
+                }
+            }
+        }
+
+        let (code, len) = fixed_literal(END_OF_BLOCK);
+        self.code(code, len);
+    }
+
+    // hands the whole bytes made so far to the writer
+    fn flush_out(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+// the CRC-32 gzip puts at the end, continuing from `crc` (0 to start)
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for byte in bytes {
+        crc = CRC_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[n] = crc;
+        n += 1;
+    }
+
+    table
+}
+
+// the Adler-32 zlib puts at the end, continuing from `adler` (1 to start)
+pub fn adler32(adler: u32, bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65_521;
+    // the most bytes that can be summed before `b` could overflow a u32
+    const CHUNK: usize = 5552;
+
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+
+    for chunk in bytes.chunks(CHUNK) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // a decoder to check the encoder against, for all three kinds of block
+    // returns the data and how many bytes of `input` it took up
+    pub(crate) fn inflate(input: &[u8]) -> (Vec<u8>, usize) {
+        let mut bits = BitReader {
+            input,
+            pos: 0,
+            bit: 0,
+        };
+        let mut out = Vec::new();
+
+        loop {
+            let last = bits.bits(1) == 1;
+
+            match bits.bits(2) {
+                0b00 => {
+                    bits.align();
+                    let len = bits.bits(16) as u16;
+                    let nlen = bits.bits(16) as u16;
+                    assert_eq!(len, !nlen, "stored block length");
+                    for _ in 0..len {
+                        out.push(bits.bits(8) as u8);
+                    }
+                }
+                0b01 => {
+                    let mut lengths = [8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    let literals = Huffman::new(&lengths);
+                    let distances = Huffman::new(&[5; 30]);
+                    codes(&mut bits, &mut out, &literals, &distances);
+                }
+                0b10 => {
+                    let (literals, distances) = dynamic_tables(&mut bits);
+                    codes(&mut bits, &mut out, &literals, &distances);
+                }
+                _ => panic!("invalid block type"),
+            }
+
+            if last {
+                break;
+            }
+        }
+
+        bits.align();
+        (out, bits.pos)
+    }
+
+    struct BitReader<'a> {
+        input: &'a [u8],
+        pos: usize,
+        bit: u32,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+
+            for i in 0..count {
+                let byte = self.input[self.pos];
+                value |= u32::from((byte >> self.bit) & 1) << i;
+
+                self.bit += 1;
+                if self.bit == 8 {
+                    self.bit = 0;
+                    self.pos += 1;
+                }
+            }
+
+            value
+        }
+
+        fn align(&mut self) {
+            if self.bit > 0 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+    }
+
+    // a canonical Huffman code, decoded a bit at a time
+    struct Huffman {
+        // how many codes there are of each length
+        counts: [u16; 16],
+        // the symbols ordered by code
+        symbols: Vec<u16>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Self {
+            let mut counts = [0; 16];
+            for len in lengths {
+                counts[*len as usize] += 1;
+            }
+            counts[0] = 0;
+
+            let mut symbols: Vec<_> = (0..lengths.len() as u16)
+                .filter(|symbol| lengths[*symbol as usize] > 0)
+                .collect();
+            symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+
+            Self { counts, symbols }
+        }
+
+        fn decode(&self, bits: &mut BitReader) -> u16 {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+
+            for len in 1..16 {
+                code |= bits.bits(1) as i32;
+                let count = i32::from(self.counts[len]);
+
+                if code - first < count {
+                    return self.symbols[(index + code - first) as usize];
+                }
+
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+
+            panic!("invalid Huffman code");
+        }
+    }
+
+    fn dynamic_tables(bits: &mut BitReader) -> (Huffman, Huffman) {
+        const ORDER: [usize; 19] = [
+            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+        ];
+
+        let literal_count = bits.bits(5) as usize + 257;
+        let distance_count = bits.bits(5) as usize + 1;
+        let code_count = bits.bits(4) as usize + 4;
+
+        let mut code_lengths = [0; 19];
+        for index in &ORDER[..code_count] {
+            code_lengths[*index] = bits.bits(3) as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths);
+
+        let mut lengths = Vec::with_capacity(literal_count + distance_count);
+        while lengths.len() < literal_count + distance_count {
+            match code_lengths.decode(bits) {
+                len @ 0..=15 => lengths.push(len as u8),
+                16 => {
+                    let previous = *lengths.last().expect("repeat with nothing before");
+                    let times = 3 + bits.bits(2);
+                    lengths.extend((0..times).map(|_| previous));
+                }
+                17 => lengths.extend((0..3 + bits.bits(3)).map(|_| 0)),
+                _ => lengths.extend((0..11 + bits.bits(7)).map(|_| 0)),
+            }
+        }
+
+        (
+            Huffman::new(&lengths[..literal_count]),
+            Huffman::new(&lengths[literal_count..]),
+        )
+    }
+
+    fn codes(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) {
+        loop {
+            let symbol = literals.decode(bits);
+
+            if symbol < 256 {
+                out.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                return;
+            }
+
+            let code = usize::from(symbol - 257);
+            let len =
+                usize::from(LENGTH_BASE[code]) + bits.bits(LENGTH_EXTRA[code].into()) as usize;
+
+            let code = usize::from(distances.decode(bits));
+            let distance =
+                usize::from(DISTANCE_BASE[code]) + bits.bits(DISTANCE_EXTRA[code].into()) as usize;
+
+            // byte by byte, a copy can overlap what it's copying
+            for _ in 0..len {
+                out.push(out[out.len() - distance]);
+            }
+        }
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut deflater = Deflater::new(Vec::new());
+        deflater.write_all(data).unwrap();
+        deflater.finish().unwrap()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let compressed = deflate(data);
+        let (inflated, used) = inflate(&compressed);
+
+        assert_eq!(inflated, data);
+        assert_eq!(used, compressed.len());
+        compressed
+    }
+
+    // bytes that don't compress, from a simple linear congruential generator
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nothing() {
+        let compressed = round_trip(b"");
+        // a fixed block with only the end of block code
+        assert_eq!(compressed, [0x03, 0x00]);
+    }
+
+    #[test]
+    fn repetitive_text_shrinks() {
+        let text = "<li>hello, world</li>\n".repeat(500);
+        let compressed = round_trip(text.as_bytes());
+
+        assert!(compressed.len() < text.len() / 20, "{}", compressed.len());
+    }
+
+    #[test]
+    fn matches_of_every_length_and_distance() {
+        let mut data = Vec::new();
+        for len in 1..300 {
+            data.extend(noise(len));
+            let start = data.len() / 3;
+            data.extend_from_within(start..start + len);
+        }
+
+        round_trip(&data);
+    }
+
+    #[test]
+    fn noise_is_stored() {
+        let data = noise(100_000);
+        let compressed = round_trip(&data);
+
+        // 4 stored blocks, 5 bytes of header each
+        assert!(
+            compressed.len() <= data.len() + 4 * 5,
+            "{}",
+            compressed.len()
+        );
+    }
+
+    #[test]
+    fn references_reach_into_earlier_blocks() {
+        // the second block is a repeat of the first, which only the history can find
+        let mut data = noise(BLOCK_SIZE);
+        data.extend_from_slice(&data.clone());
+
+        let compressed = round_trip(&data);
+        assert!(compressed.len() < BLOCK_SIZE + BLOCK_SIZE / 10);
+    }
+
+    #[test]
+    fn flush_makes_everything_so_far_decodable() {
+        let mut deflater = Deflater::new(Vec::new());
+        deflater.write_all(b"first part, ").unwrap();
+        deflater.flush().unwrap();
+
+        // a sync flush ends with an empty stored block
+        assert!(deflater.get_mut().ends_with(&[0x00, 0x00, 0xff, 0xff]));
+
+        deflater.write_all(b"second part").unwrap();
+        let compressed = deflater.finish().unwrap();
+
+        assert_eq!(inflate(&compressed).0, b"first part, second part");
+    }
+
+    #[test]
+    fn decodes_what_zlib_makes() {
+        // `zlib.compress(b"hello hello hello hello", 9)[2:-4]`, a fixed block
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01];
+        assert_eq!(inflate(&fixed).0, b"hello hello hello hello");
+
+        // the same for 60 random words from a list of 8, which zlib gives codes of their own
+        let dynamic = [
+            0x6d, 0x4f, 0x41, 0x0e, 0xc0, 0x20, 0x08, 0xfb, 0x0a, 0x5f, 0xc3, 0xcc, 0xcc, 0x25,
+            0xba, 0x99, 0xcc, 0x13, 0xaf, 0x5f, 0xb4, 0x28, 0x6c, 0xd9, 0x41, 0x02, 0x6d, 0x29,
+            0x55, 0x62, 0x63, 0xda, 0xb9, 0x14, 0xa6, 0xde, 0x71, 0xae, 0x89, 0x29, 0xf4, 0x76,
+            0x14, 0x31, 0x70, 0x8b, 0xf9, 0x2d, 0x98, 0x6f, 0x0c, 0x60, 0x17, 0xee, 0x64, 0x7e,
+            0xef, 0xdf, 0x4d, 0xcf, 0xd7, 0xfb, 0xc8, 0xd7, 0x49, 0x16, 0x08, 0x6e, 0x0a, 0x3b,
+            0x08, 0xbb, 0xb2, 0x6e, 0x87, 0xaf, 0x6d, 0x4b, 0x33, 0xc7, 0x10, 0x61, 0x44, 0x15,
+            0xef, 0x09, 0x35, 0x9c, 0xfd, 0x07, 0x94, 0x76, 0x1b, 0xea, 0xa8, 0x84, 0x5d, 0xb5,
+            0xb0, 0x5d, 0xf6, 0x00,
+        ];
+        let words = "zeta gamma eta alpha beta beta zeta alpha delta alpha beta eta eta beta delta beta eta alpha beta delta alpha eta alpha delta alpha gamma epsilon eta gamma beta epsilon gamma beta delta zeta beta beta alpha delta theta eta zeta theta theta zeta epsilon delta gamma delta beta epsilon theta zeta theta epsilon beta beta eta gamma zeta";
+        assert_eq!(dynamic[0] & 0b111, 0b101, "a last block with dynamic codes");
+        assert_eq!(inflate(&dynamic).0, words.as_bytes());
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xcbf4_3926);
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(1, &[0xff; 100_000]), {
+            let (mut a, mut b) = (1u64, 0u64);
+            for _ in 0..100_000 {
+                a = (a + 0xff) % 65_521;
+                b = (b + a) % 65_521;
+            }
+            ((b << 16) | a) as u32
+        });
+    }
+}
@@ -1,130 +1,36 @@
-use std::{
-    sync::{mpsc, Arc, Mutex},
-    thread,
-};
-
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
-}
-
-// a Job is a closure each thread needs to run
-// so use a type alias for a trait object
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
-impl ThreadPool {
-    pub fn new(size: usize) -> Self {
-        // make sure that size != 0 cuz it doesn't make sense
-        assert!(size > 0);
-
-        let (sender, receiver) = mpsc::channel();
-        // the workers should share the same receiver
-
-        let receiver = Arc::new(Mutex::new(receiver));
-        // when the thread in a worker receives a Job,
-        // it needs to mutate the receiver
-        // but multiple workers cannot mutate the receiver freely
-        // so use a Mutex to lock the receiver,
-        // allowing only 1 worker thread to access and mutate at a time
-        // then use Arc to allow workers to share the same receiver
-
-        let mut workers = Vec::with_capacity(size);
-        // vector with fixed size
-
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
-
-        Self {
-            workers,
-            sender: Some(sender),
-        }
-    }
-
-    pub fn execute<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        // send a job down the channel to be queued
-        let job = Box::new(f);
-
-        self.sender.as_ref().unwrap().send(job).unwrap();
-    }
-}
-
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        drop(self.sender.take());
-        // dropping the sender closes the channel
-        // then, all calls to `.recv()` returns an `Err` variant in the `Result`
-
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
-        }
-    }
-}
-
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>, // when exiting, the thread will be taken out of the `Option` to leave a `None`
-                                            // expects each thread to run a closure that returns the unit type `()`
-}
-
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
-
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing...");
-
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down...");
-                    break;
-                }
-            }
-
-            // compared to this implementation which gives the wrong behaviour
-            // mutex lock is held for longer than intended
-
-            //  while let Ok(job) = receiver.lock().unwrap().recv().unwrap() {
-            //      job();
-            //  }
-
-            // the ownership of the lock is based on the lifetime of the `MutexGuard<T>`
-            // within the `LockResult<MutexGuard<T>>` that the `lock` method returns
-            // which means for as long as `MutexGuard<T>` is still in scope
-            // the lock is considered to be owned, and not yet unlocked
-
-            // the expression `receiver.lock().unwrap().recv().unwrap();`
-            // creates `LockResult<MutexGuard<T>>` as temporary value after `lock()` returns
-
-            // ```let job = receiver.lock().unwrap().recv().unwrap();```
-            // this works because at the end of `let` statement,
-            // any temporary values is dropped out of scope
-
-            // a `while let`, `if let` and `match` expression
-            // does not drop the temporary values until
-            // the end of the associated block
-            // which means the lock is held until the `job()` finishes
-            // so other workers cannot acquire the lock, and hence no new jobs
-            // can be processed concurrently as one that is already running
-        });
-
-        Self {
-            id,
-            thread: Some(thread),
-        }
-    }
-}
-
-// if the OS cannot create a thread due to limited system resources
-// `thread::spawn` will panic
-// to gracefully handle this, use `std::thread::Builder` and it's `spawn` method that returns a `Result` instead
+pub mod access_log;
+pub mod chunked;
+pub mod compression;
+pub mod config;
+pub mod connection;
+pub mod date;
+pub mod deflate;
+pub mod headers;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
+pub mod pool;
+pub mod range;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod signal;
+pub mod sse;
+pub mod static_files;
+pub mod websocket;
+
+pub use access_log::{AccessLog, LogFormat};
+pub use config::{Config, ConfigError};
+pub use connection::{KeepAlive, Limits, Service};
+pub use headers::Headers;
+pub use metrics::Metrics;
+pub use middleware::{Middleware, Next};
+pub use pool::{CancellationToken, JobError, JobHandle, PoolCreationError, Priority, ThreadPool};
+pub use request::{Method, ParseError, Request, Version};
+pub use response::{Body, Response, StatusCode};
+pub use router::{Params, Router};
+pub use server::{Server, ShutdownHandle};
+pub use sse::{Broadcaster, Event, EventStream};
+pub use static_files::StaticFiles;
+pub use websocket::{Message, WebSocket, WebSocketHandler};
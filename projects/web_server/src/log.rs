@@ -0,0 +1,82 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::date::UtcDateTime;
+
+// diagnostic messages about the server itself, as opposed to the access log of requests
+// they go to stderr, and anything less severe than the current level is skipped
+// pass `format_args!(...)` rather than `format!(...)` so nothing is allocated for skipped messages
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as usize <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn error(message: impl fmt::Display) {
+    write(Level::Error, message);
+}
+
+pub fn warn(message: impl fmt::Display) {
+    write(Level::Warn, message);
+}
+
+pub fn info(message: impl fmt::Display) {
+    write(Level::Info, message);
+}
+
+pub fn debug(message: impl fmt::Display) {
+    write(Level::Debug, message);
+}
+
+fn write(level: Level, message: impl fmt::Display) {
+    if enabled(level) {
+        eprintln!("{} {:<5} {message}", UtcDateTime::now().iso8601(), level);
+    }
+}
@@ -1,60 +1,155 @@
-use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    thread,
-    time::Duration,
+use std::{env, fs, process, thread, time::Duration};
+use web_server::{
+    compression::Compress,
+    config::USAGE,
+    date::UtcDateTime,
+    log,
+    metrics::render,
+    middleware::{CatchPanic, RequestId, Timing},
+    signal, AccessLog, Broadcaster, Config, ConfigError, Event, Metrics, Response, Router, Server,
+    ShutdownHandle, StaticFiles, StatusCode, WebSocket,
 };
-use web_server::ThreadPool;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let config = Config::from_args(env::args()).unwrap_or_else(|err| {
+        if let ConfigError::Help = err {
+            println!("{USAGE}");
+            process::exit(0);
+        }
 
-    let pool = ThreadPool::new(4);
-    // create a pool of 4 threads, will be able to process 4 requests concurrently
+        eprintln!("Problem with configuration: {err}");
+        process::exit(1);
+    });
 
-    for stream in listener.incoming() {
-        // iterating through connection attempts
+    log::set_level(config.log_level);
 
-        let stream = stream.unwrap();
+    let access_log = match &config.access_log {
+        Some(path) => AccessLog::open(path, config.log_format).unwrap_or_else(|err| {
+            eprintln!("Couldn't open access log {}: {err}", path.display());
+            process::exit(1);
+        }),
+        None => AccessLog::stderr(config.log_format),
+    };
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
-    }
-}
+    let server = Server::bind(config.socket_addr(), config.workers)
+        .unwrap_or_else(|err| {
+            eprintln!("Couldn't listen on {}: {err}", config.socket_addr());
+            process::exit(1);
+        })
+        .with_max_workers(config.max_workers.unwrap_or(config.workers))
+        .with_worker_idle_timeout(config.worker_idle_timeout)
+        .with_queue_capacity(config.queue_size)
+        .with_keep_alive(config.keep_alive())
+        .with_limits(config.limits())
+        .with_shutdown_timeout(config.shutdown_timeout)
+        .with_access_log(access_log)
+        .with_middleware(RequestId::new())
+        .with_middleware(Timing)
+        .with_middleware(Compress::new())
+        .with_middleware(CatchPanic);
+    // a panicking handler still gets its request a 500, with an id and a timing to find it by
+    // and text bodies are compressed for clients that accept gzip or deflate
+    // a pool of `config.workers` threads, will be able to process that many connections concurrently
+    // and more while it's busy, up to `config.max_workers`
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    // add buffering by managing calls to std::io::Read trait methods
+    log::info(format_args!("Listening on http://{}", config.socket_addr()));
 
-    let http_request: Vec<_> = buf_reader
-        .lines()
-        // split the stream of data whenever there's a newline
-        .map(|result| result.unwrap())
-        // unwrap each result
-        .take_while(|line| !line.is_empty())
-        // http requests end with \n\n, so take each line until there is a line that is empty, created from the consecutive \n\n
-        .collect();
+    let shutdown = server.shutdown_handle();
+    signal::shutdown_on_signal(shutdown.clone()).unwrap();
+    // ctrl-c stops accepting connections and lets in-flight requests finish
 
-    println!("Request: {:#?}", http_request);
+    let metrics = server.metrics();
 
-    let request_line = &http_request[0];
+    let clock = Broadcaster::new();
+    thread::spawn({
+        let clock = clock.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            while !shutdown.is_shutdown() {
+                clock.send(
+                    Event::new(UtcDateTime::now().http_date().to_string()).with_event("tick"),
+                );
+                thread::sleep(Duration::from_secs(1));
+            }
+            // the streams run on threads of their own, which would otherwise outlive the server
+            clock.close();
+        }
+    });
+    // the time, once a second, to anyone listening on /live/clock
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
+    match server.run(routes(&config, shutdown, metrics, clock)) {
+        Ok(true) => log::info("Server stopped"),
+        Ok(false) => log::warn("Server stopped, some requests were cut off"),
+        Err(err) => log::error(format_args!("Server error: {err}")),
+    }
+}
+
+fn routes(
+    config: &Config,
+    shutdown: ShutdownHandle,
+    metrics: Metrics,
+    clock: Broadcaster,
+) -> Router {
+    let files = StaticFiles::new(&config.document_root);
+    // anything under /static/ is looked up in the document root
+    let pages = StaticFiles::new(".");
+    // only ever asked for hello.html, which browsers can then cache and revalidate
+
+    Router::new()
+        .get("/", move |request, _| {
+            pages.serve_request(request, "hello.html")
+        })
+        .get("/sleep", |_, _| {
             // simulate slow request
             thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
-        }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
-    };
+            html_file(StatusCode::Ok, "hello.html")
+        })
+        .get("/static/*path", move |request, params| {
+            files.serve_request(request, params.get("path").unwrap_or_default())
+        })
+        .get("/metrics", {
+            let metrics = metrics.clone();
+            move |_, _| metrics.response()
+        })
+        // how busy the thread pool is, for Prometheus to scrape
+        .websocket("/live/metrics", move |mut socket: WebSocket| {
+            // the same, pushed once a second for a dashboard, until the client goes away
+            let sender = socket.sender();
+            let metrics = metrics.clone();
+            thread::spawn(move || loop {
+                let text = metrics
+                    .stats()
+                    .map(|stats| render(&stats))
+                    .unwrap_or_default();
+                if sender.send_text(&text).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(1));
+            });
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
+            // answers pings and notices the close, which stops the pushing too
+            for _ in socket.messages() {}
+        })
+        .get("/live/clock", move |request, _| clock.subscribe(request))
+        // the same kind of push over Server-Sent Events, a client that reconnects gets what it missed
+        .post("/admin/shutdown", move |request, _| {
+            // only someone on this machine gets to stop the server
+            if !request
+                .peer_addr
+                .is_some_and(|addr| addr.ip().is_loopback())
+            {
+                return Response::new(StatusCode::Forbidden);
+            }
 
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+            shutdown.shutdown();
+            Response::new(StatusCode::Accepted)
+        })
+        .fallback(|_, _| html_file(StatusCode::NotFound, "404.html"))
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
+fn html_file(status: StatusCode, filename: &str) -> Response {
+    match fs::read(filename) {
+        Ok(contents) => Response::html(contents).with_status(status),
+        Err(_) => Response::new(StatusCode::InternalServerError),
+    }
 }
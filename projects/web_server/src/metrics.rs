@@ -0,0 +1,180 @@
+use std::{
+    fmt::Write,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use crate::{
+    pool::{Histogram, Stats, StatsHandle},
+    response::Response,
+};
+
+// serves the thread pool's stats in the Prometheus text format
+// ```
+// let metrics = server.metrics();
+// let router = Router::new().get("/metrics", move |_, _| metrics.response());
+// server.run(router)?;
+// ```
+// the routes are built before `Server::run` starts the pool, so the pool is attached later,
+// until then the response is empty
+#[derive(Clone, Default)]
+pub struct Metrics {
+    pool: Arc<OnceLock<StatsHandle>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // only the first pool attached counts
+    pub(crate) fn attach(&self, pool: StatsHandle) {
+        let _ = self.pool.set(pool);
+    }
+
+    pub fn stats(&self) -> Option<Stats> {
+        self.pool.get().map(StatsHandle::stats)
+    }
+
+    pub fn response(&self) -> Response {
+        let body = self.stats().map(|stats| render(&stats)).unwrap_or_default();
+
+        Response::ok()
+            .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .with_header("Cache-Control", "no-store")
+            .with_body(body)
+    }
+}
+
+// formats `stats` like this, one metric after another:
+// ```
+// # HELP web_server_pool_workers Worker threads running.
+// # TYPE web_server_pool_workers gauge
+// web_server_pool_workers 4
+// ```
+pub fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+
+    let gauges = [
+        ("workers", "Worker threads running.", stats.workers),
+        (
+            "max_workers",
+            "Worker threads the pool may grow to.",
+            stats.max_workers,
+        ),
+        (
+            "active_workers",
+            "Workers running a job right now.",
+            stats.active,
+        ),
+        ("queued_jobs", "Jobs waiting for a worker.", stats.queued),
+    ];
+
+    for (name, help, value) in gauges {
+        metric(&mut out, name, help, "gauge");
+        let _ = writeln!(out, "web_server_pool_{name} {value}");
+    }
+
+    let counters = [
+        (
+            "jobs_completed_total",
+            "Jobs that ran to completion.",
+            stats.completed,
+        ),
+        ("jobs_panicked_total", "Jobs that panicked.", stats.panicked),
+        (
+            "jobs_rejected_total",
+            "Jobs turned away because the queue was full.",
+            stats.rejected,
+        ),
+    ];
+
+    for (name, help, value) in counters {
+        metric(&mut out, name, help, "counter");
+        let _ = writeln!(out, "web_server_pool_{name} {value}");
+    }
+
+    histogram(
+        &mut out,
+        "job_wait_seconds",
+        "Time jobs spent queued before a worker started them.",
+        &stats.wait_time,
+    );
+    histogram(
+        &mut out,
+        "job_run_seconds",
+        "Time jobs took to run.",
+        &stats.run_time,
+    );
+
+    out
+}
+
+fn metric(out: &mut String, name: &str, help: &str, kind: &str) {
+    // writing to a `String` can't fail
+    let _ = writeln!(out, "# HELP web_server_pool_{name} {help}");
+    let _ = writeln!(out, "# TYPE web_server_pool_{name} {kind}");
+}
+
+// every bucket with its upper bound as `le`, then the total as the `+Inf` bucket, the sum and the count
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    metric(out, name, help, "histogram");
+
+    for (bound, count) in &histogram.buckets {
+        let _ = writeln!(
+            out,
+            "web_server_pool_{name}_bucket{{le=\"{}\"}} {count}",
+            seconds(*bound)
+        );
+    }
+
+    let count = histogram.count;
+    let _ = writeln!(out, "web_server_pool_{name}_bucket{{le=\"+Inf\"}} {count}");
+    let _ = writeln!(out, "web_server_pool_{name}_sum {}", seconds(histogram.sum));
+    let _ = writeln!(out, "web_server_pool_{name}_count {count}");
+}
+
+// Prometheus measures time in seconds, as a float
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pool::Builder, StatusCode};
+
+    #[test]
+    fn renders_gauges_counters_and_histograms() {
+        let pool = Builder::new(2).build().unwrap();
+        pool.submit(|| ()).wait().unwrap();
+
+        let text = render(&pool.stats());
+
+        assert!(text.contains("# TYPE web_server_pool_workers gauge\nweb_server_pool_workers 2\n"));
+        assert!(text.contains("web_server_pool_jobs_completed_total 1\n"));
+        assert!(text.contains("# TYPE web_server_pool_job_wait_seconds histogram\n"));
+        assert!(text.contains("web_server_pool_job_wait_seconds_bucket{le=\"0.0001\"} "));
+        assert!(text.contains("web_server_pool_job_run_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("web_server_pool_job_run_seconds_count 1\n"));
+
+        // every line is a comment or a name and a number
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "bad sample {line:?}");
+        }
+    }
+
+    #[test]
+    fn empty_until_a_pool_is_attached() {
+        let metrics = Metrics::new();
+        let response = metrics.response();
+
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body.len(), Some(0));
+
+        let pool = Builder::new(1).build().unwrap();
+        metrics.clone().attach(pool.stats_handle());
+        assert_eq!(metrics.stats().map(|stats| stats.workers), Some(1));
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    panic::{self, AssertUnwindSafe},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use crate::{
+    log,
+    pool::panic_message,
+    request::Request,
+    response::{Response, StatusCode},
+};
+
+// wraps the handling of every request, to do something before the handler, after it, or instead of it
+// ```
+// let server = Server::bind("127.0.0.1:7878", 4)?
+//     .with_middleware(RequestId::new())
+//     .with_middleware(|request: &mut Request, next: Next| {
+//         if request.header("Authorization").is_none() {
+//             return Response::new(StatusCode::Unauthorized);
+//         }
+//         next.run(request)
+//     });
+// ```
+// middleware runs in the order it was added, so the first one added sees the request first
+// and the response last
+pub trait Middleware: Send + Sync + 'static {
+    // calls `next.run(request)` to pass the request on, or answers it without doing so
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut Request, Next<'_>) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+// the rest of the chain after a middleware, ending with the router
+pub struct Next<'a> {
+    rest: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Fn(&Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    // the whole chain, `middleware` in order and then `endpoint`
+    pub(crate) fn new(
+        middleware: &'a [Box<dyn Middleware>],
+        endpoint: &'a dyn Fn(&Request) -> Response,
+    ) -> Self {
+        Self {
+            rest: middleware,
+            endpoint,
+        }
+    }
+
+    pub fn run(self, request: &mut Request) -> Response {
+        match self.rest.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+// gives every request an id in the `X-Request-Id` header, which is sent back with the response
+// a request that arrives with one, say from a load balancer, keeps it, so the same id can be followed
+// through every server it passed
+// handlers and later middleware see the id in the request's headers
+pub struct RequestId {
+    // random for every server, so ids from two servers behind the same balancer don't collide
+    prefix: u32,
+    next: AtomicU64,
+}
+
+// unpredictable enough to tell apart ids and boundaries from different runs, not for anything secret
+// every `RandomState` gets random keys, and hashing nothing with them is the only randomness std has to offer
+pub(crate) fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// ids that came with the request are only trusted this far, anything else is replaced
+const MAX_REQUEST_ID_LEN: usize = 200;
+
+impl RequestId {
+    pub fn new() -> Self {
+        let prefix = random_u64() as u32;
+
+        Self {
+            prefix,
+            next: AtomicU64::new(1),
+        }
+    }
+
+    fn generate(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{:08x}-{n:08x}", self.prefix)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let id = match request.header("X-Request-Id") {
+            Some(id) if is_valid_request_id(id) => id.to_string(),
+            _ => self.generate(),
+        };
+        request.headers.set("X-Request-Id", id.clone());
+
+        let mut response = next.run(request);
+        response.headers.set("X-Request-Id", id);
+        response
+    }
+}
+
+// an id is copied into the response as is, so it has to be something harmless to echo back
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+// adds how long the rest of the chain took to the response, as `Server-Timing: app;dur=1.234`
+// in milliseconds, which browsers show in their network tab
+// a streamed body is produced after this, so its time isn't included
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let started = Instant::now();
+        let mut response = next.run(request);
+        let millis = started.elapsed().as_secs_f64() * 1000.0;
+
+        response
+            .headers
+            .append("Server-Timing", format!("app;dur={millis:.3}"));
+        response
+    }
+}
+
+// answers 500 Internal Server Error when the rest of the chain panics
+// without it the panic is still caught by the worker, but the connection is just closed,
+// and the client is left guessing
+// a panic while a streamed body is being written happens after this, the response is cut short
+// and the client sees the connection close
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let method = request.method;
+        let path = request.path.clone();
+
+        // the request may be left half changed, but nothing uses it after a panic
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(request))) {
+            Ok(response) => response,
+            Err(payload) => {
+                let message = panic_message(&*payload);
+                log::error(format_args!(
+                    "Handler for {} {path} panicked: {message}",
+                    method.as_str()
+                ));
+
+                // whatever state the connection's handler was in, don't trust it for another request
+                Response::new(StatusCode::InternalServerError).with_header("Connection", "close")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use std::sync::{Arc, Mutex};
+
+    fn run(middleware: &[Box<dyn Middleware>], request: &mut Request) -> Response {
+        let endpoint = |request: &Request| {
+            if request.path == "/panic" {
+                panic!("handler failed");
+            }
+            let id = request.header("X-Request-Id").unwrap_or("none");
+            Response::text(id.to_string())
+        };
+
+        Next::new(middleware, &endpoint).run(request)
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body.as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn runs_in_the_order_added() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let tracer = |name: &'static str| {
+            let order = Arc::clone(&order);
+            Box::new(move |request: &mut Request, next: Next<'_>| {
+                order.lock().unwrap().push(format!("{name} before"));
+                let response = next.run(request);
+                order.lock().unwrap().push(format!("{name} after"));
+                response
+            }) as Box<dyn Middleware>
+        };
+
+        let chain = [tracer("outer"), tracer("inner")];
+        run(&chain, &mut Request::new(Method::Get, "/"));
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["outer before", "inner before", "inner after", "outer after"]
+        );
+    }
+
+    #[test]
+    fn middleware_can_answer_without_the_handler() {
+        let deny: Box<dyn Middleware> =
+            Box::new(|_: &mut Request, _: Next<'_>| Response::new(StatusCode::Unauthorized));
+
+        let response = run(&[deny], &mut Request::new(Method::Get, "/panic"));
+        assert_eq!(response.status, StatusCode::Unauthorized);
+    }
+
+    #[test]
+    fn request_ids_are_generated_and_shared_with_the_handler() {
+        let chain: [Box<dyn Middleware>; 1] = [Box::new(RequestId::new())];
+
+        let first = run(&chain, &mut Request::new(Method::Get, "/"));
+        let second = run(&chain, &mut Request::new(Method::Get, "/"));
+
+        let id = first.headers.get("X-Request-Id").unwrap();
+        assert_eq!(body(&first), id);
+        assert_eq!(id.len(), 17);
+        assert_ne!(second.headers.get("X-Request-Id"), Some(id));
+    }
+
+    #[test]
+    fn incoming_request_ids_are_kept_if_sensible() {
+        let chain: [Box<dyn Middleware>; 1] = [Box::new(RequestId::new())];
+
+        let mut request = Request::new(Method::Get, "/");
+        request.headers.set("X-Request-Id", "from-the-balancer");
+        let response = run(&chain, &mut request);
+        assert_eq!(
+            response.headers.get("X-Request-Id"),
+            Some("from-the-balancer")
+        );
+
+        let mut request = Request::new(Method::Get, "/");
+        request.headers.set("X-Request-Id", "two words");
+        let response = run(&chain, &mut request);
+        assert_ne!(response.headers.get("X-Request-Id"), Some("two words"));
+    }
+
+    #[test]
+    fn timing_header_in_milliseconds() {
+        let chain: [Box<dyn Middleware>; 1] = [Box::new(Timing)];
+
+        let response = run(&chain, &mut Request::new(Method::Get, "/"));
+
+        let timing = response.headers.get("Server-Timing").unwrap();
+        let millis = timing.strip_prefix("app;dur=").unwrap();
+        assert!(millis.parse::<f64>().unwrap() >= 0.0);
+    }
+
+    #[test]
+    fn panics_become_500() {
+        let chain: [Box<dyn Middleware>; 2] = [Box::new(RequestId::new()), Box::new(CatchPanic)];
+
+        let response = run(&chain, &mut Request::new(Method::Get, "/panic"));
+
+        assert_eq!(response.status, StatusCode::InternalServerError);
+        assert_eq!(response.headers.get("Connection"), Some("close"));
+        // middleware outside `CatchPanic` still sees the response
+        assert!(response.headers.contains("X-Request-Id"));
+    }
+}
@@ -0,0 +1,1838 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    error::Error,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::log;
+
+use queue::Queue;
+use stats::Metrics;
+use timer::{Task, Timer};
+
+pub use scope::Scope;
+pub use stats::{Histogram, Stats};
+pub use timer::CancellationToken;
+
+mod parallel;
+mod queue;
+mod scope;
+mod stats;
+mod timer;
+
+// configures a `ThreadPool` before any of its threads are started
+// ```
+// let pool = Builder::new(4)
+//     .with_name("web-worker")
+//     .with_stack_size(256 * 1024)
+//     .with_max_workers(16)
+//     .build()?;
+// ```
+pub struct Builder {
+    size: usize,
+    max_workers: Option<usize>,
+    idle_timeout: Duration,
+    name: String,
+    // `None` leaves it to std, which uses 2 MiB unless `RUST_MIN_STACK` says otherwise
+    stack_size: Option<usize>,
+    // `None` queues as many jobs as are submitted
+    queue_capacity: Option<usize>,
+    overflow: Overflow,
+}
+
+impl Builder {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            max_workers: None,
+            idle_timeout: Duration::from_secs(60),
+            name: String::from("worker"),
+            stack_size: None,
+            queue_capacity: None,
+            overflow: Overflow::Block,
+        }
+    }
+
+    // lets the pool start more workers while jobs are waiting for one, up to `max` in total
+    // the workers above `size` stop again once they've been idle for the idle timeout
+    pub fn with_max_workers(mut self, max: usize) -> Self {
+        self.max_workers = Some(max);
+        self
+    }
+
+    // how long a worker beyond the pool's `size` waits for a job before it stops, a minute by default
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    // threads are named `<name>-<id>`, which shows up in panic messages, debuggers and `top -H`
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    // at most `capacity` jobs wait for a worker, further ones are handled by the overflow policy
+    // with a capacity of 0 a job is only accepted if a worker is idle and waiting for one
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    // what to do with a job when the queue is full, only matters with a queue capacity
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        let sizing = Sizing {
+            min: self.size,
+            max: self.max_workers.unwrap_or(self.size),
+            idle_timeout: self.idle_timeout,
+        };
+
+        let name = self.name.clone();
+
+        ThreadPool::build_with(
+            sizing,
+            name,
+            self.queue_capacity,
+            self.overflow,
+            move |id| {
+                let builder = thread::Builder::new().name(format!("{}-{id}", self.name));
+
+                match self.stack_size {
+                    Some(bytes) => builder.stack_size(bytes),
+                    None => builder,
+                }
+            },
+        )
+    }
+}
+
+// what a bounded pool does with a job that arrives while its queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // wait until there's room, which slows the caller down to the pace of the workers
+    Block,
+    // refuse the job, `try_execute` returns `QueueFull` and `execute` logs and drops it
+    Reject,
+    // throw away the job that has been waiting longest to make room
+    // `JobHandle`s of dropped jobs see `JobError::Lost`
+    // scoped jobs are never thrown away, they run on the thread that pushed them out instead
+    DropOldest,
+    // run the job right away on the thread that submitted it
+    CallerRuns,
+}
+
+// the order waiting jobs are picked up in, jobs of the same priority run in the order they were queued
+// a job already running is never interrupted for one of higher priority
+// ```
+// pool.priority(Priority::Low).execute_every(Duration::from_secs(60), expire_sessions);
+// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    const COUNT: usize = 3;
+
+    // where jobs of this priority are kept in the queue, highest first
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// returned by `try_execute` when the queue is full and the overflow policy is `Reject`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the thread pool's queue is full")
+    }
+}
+
+impl Error for QueueFull {}
+
+#[derive(Debug)]
+pub enum PoolCreationError {
+    // a pool without workers would accept jobs and never run them
+    ZeroSize,
+    // `with_max_workers` was given fewer workers than the pool starts with
+    MaxBelowSize { size: usize, max: usize },
+    // the OS refused to start worker `id`, the workers started before it have been shut down again
+    Spawn { id: usize, source: io::Error },
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroSize => write!(f, "a thread pool needs at least one worker"),
+            PoolCreationError::MaxBelowSize { size, max } => {
+                write!(
+                    f,
+                    "a thread pool of {size} workers can't have a maximum of {max}"
+                )
+            }
+            PoolCreationError::Spawn { id, source } => {
+                write!(f, "couldn't start worker {id}: {source}")
+            }
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroSize | PoolCreationError::MaxBelowSize { .. } => None,
+            PoolCreationError::Spawn { source, .. } => Some(source),
+        }
+    }
+}
+
+// the result of a job started with `ThreadPool::submit`
+// ```
+// let handle = pool.submit(|| 6 * 7);
+// assert_eq!(handle.wait()?, 42);
+// ```
+// dropping the handle doesn't cancel the job, its result is just thrown away
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<Result<T, JobError>>,
+}
+
+impl<T> JobHandle<T> {
+    // blocks until the job has finished
+    pub fn wait(self) -> Result<T, JobError> {
+        self.receiver.recv().unwrap_or(Err(JobError::Lost))
+    }
+
+    // waits at most `timeout` for the job to finish
+    // gives the handle back if it's still running, so the caller can wait again later
+    pub fn try_wait(self, timeout: Duration) -> Result<Result<T, JobError>, Self> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => Ok(result),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(self),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Ok(Err(JobError::Lost)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    // the job panicked, with this message
+    // the worker that ran it survives and goes on to the next job
+    Panicked(String),
+    // the job was thrown away before it could produce a result
+    Lost,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(message) => write!(f, "job panicked: {message}"),
+            JobError::Lost => write!(f, "job was dropped before it finished"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+// `panic!` payloads are a `&str` or a `String` unless someone used `panic_any`
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("<non-string panic payload>")
+    }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    overflow: Overflow,
+}
+
+// a Job is a closure each thread needs to run
+// so use a type alias for a trait object
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// makes the `thread::Builder` for worker `id`, so a replacement gets the same name and stack size
+type ThreadBuilder = Box<dyn Fn(usize) -> thread::Builder + Send + Sync>;
+
+// how many workers a pool runs, see `Builder::with_max_workers`
+#[derive(Debug, Clone, Copy)]
+struct Sizing {
+    min: usize,
+    max: usize,
+    idle_timeout: Duration,
+}
+
+// what every worker thread needs, including the ones started to replace a dead worker
+struct Shared {
+    queue: Queue,
+    // delayed and periodic jobs, until they're due and go into the queue
+    timer: Timer,
+    // what the threads are named after, the timer thread is `<name>-timer`
+    name: String,
+    thread_builder: ThreadBuilder,
+    // the thread of every worker by id, a slot without a thread is reused by the next worker started
+    workers: Mutex<Vec<ThreadSlot>>,
+    // workers running, counting the ones being started
+    live: AtomicUsize,
+    // the pool keeps `min` workers, and starts more up to `max` while jobs are waiting
+    // both change with `resize`, which holds the `workers` lock while it does
+    min: AtomicUsize,
+    max: AtomicUsize,
+    idle_timeout: Duration,
+    metrics: Metrics,
+}
+
+thread_local! {
+    // the pool and worker id of the worker running on this thread, if it is one
+    // the pool is only compared against, never dereferenced
+    static CURRENT_WORKER: Cell<Option<(*const Shared, usize)>> = const { Cell::new(None) };
+}
+
+impl ThreadPool {
+    // panics if the pool can't be created, see `build` for a version that doesn't
+    pub fn new(size: usize) -> Self {
+        Self::build(size).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    // a pool of `size` workers with the default thread names and stack size
+    pub fn build(size: usize) -> Result<Self, PoolCreationError> {
+        Builder::new(size).build()
+    }
+
+    fn build_with(
+        sizing: Sizing,
+        name: String,
+        queue_capacity: Option<usize>,
+        overflow: Overflow,
+        thread_builder: impl Fn(usize) -> thread::Builder + Send + Sync + 'static,
+    ) -> Result<Self, PoolCreationError> {
+        // make sure that size != 0 cuz it doesn't make sense
+        if sizing.min == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+
+        if sizing.max < sizing.min {
+            return Err(PoolCreationError::MaxBelowSize {
+                size: sizing.min,
+                max: sizing.max,
+            });
+        }
+
+        let shared = Arc::new(Shared {
+            queue: Queue::new(sizing.max, queue_capacity),
+            timer: Timer::default(),
+            name,
+            thread_builder: Box::new(thread_builder),
+            workers: Mutex::new(Vec::with_capacity(sizing.max)),
+            live: AtomicUsize::new(0),
+            min: AtomicUsize::new(sizing.min),
+            max: AtomicUsize::new(sizing.max),
+            idle_timeout: sizing.idle_timeout,
+            metrics: Metrics::default(),
+        });
+        // every worker needs the queue, and a replacement worker needs the thread builder,
+        // so share them with an Arc
+
+        let pool = Self { shared, overflow };
+
+        // on error, dropping the pool closes the queue and joins the workers started so far,
+        // so a failed build doesn't leave threads behind
+        pool.shared.start_missing_workers()?;
+
+        Ok(pool)
+    }
+
+    // the number of workers right now, between the pool's size and its maximum
+    // jobs that panic don't change it, their workers are replaced
+    pub fn size(&self) -> usize {
+        self.shared.live.load(Ordering::SeqCst)
+    }
+
+    // changes the number of workers the pool keeps to `n`, and moves its maximum by as much,
+    // so a pool of 4 to 16 workers resized to 8 runs 8 to 20
+    // missing workers are started right away, while surplus ones stop once they've finished their job
+    pub fn resize(&self, n: usize) -> Result<(), PoolCreationError> {
+        if n == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+
+        let shared = &self.shared;
+
+        {
+            let _workers = lock(&shared.workers);
+
+            let spread = shared.max.load(Ordering::SeqCst) - shared.min.load(Ordering::SeqCst);
+            shared.min.store(n, Ordering::SeqCst);
+            shared.max.store(n + spread, Ordering::SeqCst);
+        }
+
+        log::info(format_args!("Resized thread pool to {n} workers"));
+
+        // idle workers check whether they're still needed
+        shared.queue.wake_all();
+
+        shared.start_missing_workers()
+    }
+
+    // a job that panics doesn't take its worker down, the panic is logged and the worker moves on
+    // runs at `Priority::Normal`, see `priority` for the others
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.priority(Priority::Normal).execute(f);
+    }
+
+    // like `execute`, but tells the caller when the job was refused because the queue is full
+    // that only happens with a queue capacity and the `Reject` overflow policy
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.priority(Priority::Normal).try_execute(f)
+    }
+
+    // runs `f` once `delay` has passed, unless the returned token is cancelled first
+    // the job waits outside the queue until it's due, then goes in ahead of the overflow policy,
+    // it was accepted when it was handed over and isn't refused later
+    // jobs still waiting when the pool shuts down never run
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> CancellationToken
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.priority(Priority::Normal).execute_after(delay, f)
+    }
+
+    // runs `f` every `interval`, the first time one `interval` from now, until the token is cancelled
+    // a run is only queued once the one before has finished, so runs never overlap,
+    // and ticks missed because a run took too long are skipped rather than caught up on
+    // a run that panics is logged like any other job, and the next one still happens
+    // panics if `interval` is zero
+    pub fn execute_every<F>(&self, interval: Duration, f: F) -> CancellationToken
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.priority(Priority::Normal).execute_every(interval, f)
+    }
+
+    // submits jobs at `priority` instead of `Normal`
+    // ```
+    // pool.priority(Priority::High).execute(move || handle_connection(stream));
+    // pool.priority(Priority::Low).execute_every(Duration::from_secs(3600), rotate_logs);
+    // ```
+    pub fn priority(&self, priority: Priority) -> Prioritized<'_> {
+        Prioritized {
+            pool: self,
+            priority,
+        }
+    }
+
+    // queues `job`, or applies the overflow policy if there's no room
+    // gives the job back if the policy is to refuse it
+    fn schedule(&self, job: Job, priority: Priority) -> Result<(), Job> {
+        let job = match self.try_schedule(job, priority) {
+            Ok(()) => return Ok(()),
+            Err(job) => job,
+        };
+
+        let queue = &self.shared.queue;
+        let worker = self.current_worker();
+
+        match self.overflow {
+            Overflow::Block => {
+                queue.reserve_blocking();
+                queue.push(job, priority, worker);
+            }
+            Overflow::Reject => return Err(job),
+            Overflow::DropOldest => self.replace_oldest(job, priority, worker),
+            Overflow::CallerRuns => {
+                if let Some(message) = self.shared.run_job(job, Instant::now()) {
+                    log::error(format_args!("Job panicked on the caller: {message}"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // queues `job` if there's room, or a worker to add, and gives it back otherwise
+    // whatever the overflow policy is
+    fn try_schedule(&self, job: Job, priority: Priority) -> Result<(), Job> {
+        let queue = &self.shared.queue;
+
+        // a job submitted by one of our own workers stays with that worker, unless another one steals it
+        let worker = self.current_worker();
+
+        if queue.try_reserve() {
+            queue.push(job, priority, worker);
+
+            // more jobs waiting than idle workers to take them
+            if queue.len() > queue.sleepers() {
+                self.shared.grow();
+            }
+
+            return Ok(());
+        }
+
+        // a full queue is the clearest sign the pool is too small,
+        // and the new worker will take a job off the queue as soon as it's running
+        if self.shared.grow() {
+            queue.reserve_anyway();
+            queue.push(job, priority, worker);
+            return Ok(());
+        }
+
+        Err(job)
+    }
+
+    // the id of the worker running on this thread, if it's one of this pool's
+    fn current_worker(&self) -> Option<usize> {
+        CURRENT_WORKER.with(|current| match current.get() {
+            Some((pool, id)) if ptr::eq(pool, Arc::as_ptr(&self.shared)) => Some(id),
+            _ => None,
+        })
+    }
+
+    // makes room for `job` by throwing away the job that has waited longest at the lowest priority
+    fn replace_oldest(&self, job: Job, priority: Priority, worker: Option<usize>) {
+        let queue = &self.shared.queue;
+
+        loop {
+            if queue.try_reserve() {
+                queue.push(job, priority, worker);
+                return;
+            }
+
+            match queue.take_oldest() {
+                Some(oldest) => {
+                    drop(oldest);
+                    self.shared.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                    log::debug("Thread pool queue is full, dropped the oldest job");
+                }
+                None => {
+                    // the workers may have emptied the queue in the meantime
+                    if queue.try_reserve() {
+                        queue.push(job, priority, worker);
+                    } else {
+                        // nothing is waiting, there's just no idle worker and no capacity for waiting,
+                        // so the new job is the one to go
+                        self.shared.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                        log::debug("No idle worker, dropped a job");
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    // the number of jobs waiting for a worker, not counting the ones running
+    pub fn queued(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    // a snapshot of the pool's workers, jobs and how long they take
+    pub fn stats(&self) -> Stats {
+        self.shared.stats()
+    }
+
+    // something to get `stats` from where the pool itself can't be reached,
+    // like a request handler running on one of its workers
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    // like `execute`, but hands back the closure's return value through a `JobHandle`
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.priority(Priority::Normal).submit(f)
+    }
+
+    // closes the queue, then gives the workers up to `timeout` to finish the jobs they have
+    // returns false if some workers were still busy at the deadline
+    // those threads are detached and left running, instead of blocking forever like `drop` would
+    pub fn join_timeout(self, timeout: Duration) -> bool {
+        self.shared.timer.close();
+        self.shared.queue.close();
+
+        let deadline = Instant::now() + timeout;
+        let mut all_finished = true;
+
+        for (id, slot) in self.shared.slots().iter().enumerate() {
+            // a worker that died and was replaced while we waited leaves a new thread in its slot
+            while let Some(thread) = take_thread(slot) {
+                while !thread.is_finished() && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(10));
+                }
+
+                if thread.is_finished() {
+                    let _ = thread.join();
+                } else {
+                    log::warn(format_args!("Worker {id} still busy at shutdown deadline"));
+                    all_finished = false;
+                    // dropping a `JoinHandle` detaches the thread rather than stopping it
+                    break;
+                }
+            }
+        }
+
+        all_finished
+    }
+}
+
+// submits jobs to a pool at one priority, see `ThreadPool::priority`
+#[derive(Clone, Copy)]
+pub struct Prioritized<'a> {
+    pool: &'a ThreadPool,
+    priority: Priority,
+}
+
+impl Prioritized<'_> {
+    // see `ThreadPool::execute`
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.try_execute(f).is_err() {
+            log::warn("Thread pool queue is full, dropped a job");
+        }
+    }
+
+    // see `ThreadPool::try_execute`
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.pool.shared;
+
+        self.pool.schedule(Box::new(f), self.priority).map_err(|_| {
+            shared.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+            QueueFull
+        })
+    }
+
+    // see `ThreadPool::submit`
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // every job gets its own channel with room for exactly one result,
+        // so sending never blocks the worker even if nobody ever waits
+        let (sender, receiver) = mpsc::sync_channel(1);
+
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f))
+                .map_err(|payload| JobError::Panicked(panic_message(&*payload)));
+            // the closure is only run once and its result is sent straight back,
+            // so nothing observes state it might have left half-updated
+
+            // an error just means the handle was dropped and nobody wants the result
+            let _ = sender.send(result);
+        });
+
+        JobHandle { receiver }
+    }
+
+    // see `ThreadPool::execute_after`
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> CancellationToken
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let token = CancellationToken::default();
+        let task = Task::Once(Box::new(f));
+
+        self.pool
+            .shared
+            .schedule_at(Instant::now() + delay, self.priority, token.clone(), task);
+
+        token
+    }
+
+    // see `ThreadPool::execute_every`
+    pub fn execute_every<F>(&self, interval: Duration, f: F) -> CancellationToken
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(
+            !interval.is_zero(),
+            "execute_every needs an interval above zero"
+        );
+
+        let token = CancellationToken::default();
+        let task = Task::Every {
+            f: Arc::new(f),
+            interval,
+        };
+
+        self.pool.shared.schedule_at(
+            Instant::now() + interval,
+            self.priority,
+            token.clone(),
+            task,
+        );
+
+        token
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // delayed jobs that aren't due yet are dropped, they'd only keep the pool waiting
+        self.shared.timer.close();
+        self.shared.queue.close();
+        // once the queue is closed and empty, waiting for the next job returns `None`
+        // and the workers stop
+
+        for (id, slot) in self.shared.slots().iter().enumerate() {
+            log::debug(format_args!("Shutting down worker {id}"));
+
+            while let Some(thread) = take_thread(slot) {
+                // `Err` only means the thread panicked, and it has been replaced by then
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+// the thread currently doing the work of one worker
+// a dying thread puts its replacement in here, so shared between the pool and the thread
+// when exiting, the thread will be taken out of the `Option` to leave a `None`
+// expects each thread to run a closure that returns the unit type `()`
+type ThreadSlot = Arc<Mutex<Option<thread::JoinHandle<()>>>>;
+
+// the guard is dropped before this returns, unlike with `while let Some(_) = lock(slot).take()`,
+// which holds it for the whole loop body, and the thread being joined may need the slot itself,
+// to clear it when retiring or to store its replacement when dying
+fn take_thread(slot: &ThreadSlot) -> Option<thread::JoinHandle<()>> {
+    lock(slot).take()
+}
+
+impl Shared {
+    // starts workers until there are `min`
+    fn start_missing_workers(self: &Arc<Self>) -> Result<(), PoolCreationError> {
+        while self.reserve_worker(&self.min) {
+            self.start_worker()?;
+        }
+
+        Ok(())
+    }
+
+    // starts one more worker if the pool is allowed to grow, and returns whether it did
+    fn grow(self: &Arc<Self>) -> bool {
+        if !self.reserve_worker(&self.max) {
+            return false;
+        }
+
+        match self.start_worker() {
+            Ok(id) => {
+                log::debug(format_args!("Jobs are waiting, started worker {id}"));
+                true
+            }
+            Err(err) => {
+                log::warn(format_args!("Couldn't grow the thread pool: {err}"));
+                false
+            }
+        }
+    }
+
+    // counts one more live worker, unless that would be more than `limit`
+    fn reserve_worker(&self, limit: &AtomicUsize) -> bool {
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live < limit.load(Ordering::SeqCst)).then_some(live + 1)
+            })
+            .is_ok()
+    }
+
+    // starts the thread of a worker already counted by `reserve_worker`, and returns its id
+    fn start_worker(self: &Arc<Self>) -> Result<usize, PoolCreationError> {
+        let mut workers = lock(&self.workers);
+
+        let id = match workers.iter().position(|slot| lock(slot).is_none()) {
+            Some(id) => id,
+            None => {
+                workers.push(Arc::new(Mutex::new(None)));
+                workers.len() - 1
+            }
+        };
+
+        spawn_thread(id, Arc::clone(self), Arc::clone(&workers[id])).map_err(|source| {
+            self.live.fetch_sub(1, Ordering::SeqCst);
+            PoolCreationError::Spawn { id, source }
+        })?;
+
+        Ok(id)
+    }
+
+    // called by a worker that found no job, stops counting it if the pool can do without it
+    // that's when there are more workers than the maximum,
+    // or more than the minimum and this one has been idle for the idle timeout
+    fn retire(&self, idle: Duration) -> bool {
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                let surplus = live > self.max.load(Ordering::SeqCst)
+                    || (live > self.min.load(Ordering::SeqCst) && idle >= self.idle_timeout);
+
+                surplus.then_some(live - 1)
+            })
+            .is_ok()
+    }
+
+    // how long a worker idle since `idle_since` should wait for a job before checking `retire`
+    // a worker the pool needs waits for as long as it takes
+    fn idle_deadline(&self, idle_since: Instant) -> Option<Instant> {
+        let live = self.live.load(Ordering::SeqCst);
+
+        if live > self.max.load(Ordering::SeqCst) {
+            Some(idle_since)
+        } else if live > self.min.load(Ordering::SeqCst) {
+            Some(idle_since + self.idle_timeout)
+        } else {
+            None
+        }
+    }
+
+    fn slots(&self) -> Vec<ThreadSlot> {
+        lock(&self.workers).clone()
+    }
+
+    // runs `job`, recording it in the metrics, and returns its panic message if it panicked
+    fn run_job(&self, job: Job, queued_at: Instant) -> Option<String> {
+        let metrics = &self.metrics;
+        // the job only stops counting as active once it's counted as done,
+        // so anyone who sees no active jobs sees all of them in `completed` or `panicked`
+        let _active = metrics.start_job();
+
+        let started = Instant::now();
+        metrics.wait_time.record(started - queued_at);
+
+        let panicked = run_job(job);
+
+        metrics.run_time.record(started.elapsed());
+        if panicked.is_some() {
+            metrics.panicked.fetch_add(1, Ordering::Relaxed);
+        } else {
+            metrics.completed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        panicked
+    }
+
+    // hands `task` to the timer, to be queued at `due`
+    fn schedule_at(
+        self: &Arc<Self>,
+        due: Instant,
+        priority: Priority,
+        token: CancellationToken,
+        task: Task,
+    ) {
+        let start = || {
+            let shared = Arc::clone(self);
+
+            thread::Builder::new()
+                .name(format!("{}-timer", self.name))
+                .spawn(move || {
+                    while let Some(entry) = shared.timer.next() {
+                        shared.fire(entry.task, entry.due, entry.priority, entry.token);
+                    }
+                })
+        };
+
+        if let Err(err) = self.timer.add(due, priority, token, task, start) {
+            log::error(format_args!(
+                "Couldn't start the timer thread, dropped a job: {err}"
+            ));
+        }
+    }
+
+    // queues a delayed or periodic job that has come due, unless it was cancelled
+    fn fire(
+        self: &Arc<Self>,
+        task: Task,
+        due: Instant,
+        priority: Priority,
+        token: CancellationToken,
+    ) {
+        if token.is_cancelled() {
+            return;
+        }
+
+        let job: Job = match task {
+            Task::Once(job) => job,
+            Task::Every { f, interval } => {
+                let shared = Arc::clone(self);
+
+                Box::new(move || {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| f()));
+
+                    // the next run is only scheduled once this one is done, so they never overlap
+                    if !token.is_cancelled() {
+                        let next = timer::next_due(due, interval);
+                        let task = Task::Every { f, interval };
+                        shared.schedule_at(next, priority, token, task);
+                    }
+
+                    // the worker logs and counts the panic like any other
+                    if let Err(payload) = result {
+                        panic::resume_unwind(payload);
+                    }
+                })
+            }
+        };
+
+        // accepted when it was scheduled, so it's queued even if the queue is full by now
+        self.queue.reserve_anyway();
+        self.queue.push(job, priority, None);
+
+        if self.queue.len() > self.queue.sleepers() {
+            self.grow();
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let metrics = &self.metrics;
+
+        Stats {
+            workers: self.live.load(Ordering::SeqCst),
+            max_workers: self.max.load(Ordering::SeqCst),
+            active: metrics.active.load(Ordering::Acquire),
+            queued: self.queue.len(),
+            completed: metrics.completed.load(Ordering::Relaxed),
+            panicked: metrics.panicked.load(Ordering::Relaxed),
+            rejected: metrics.rejected.load(Ordering::Relaxed),
+            wait_time: metrics.wait_time.snapshot(),
+            run_time: metrics.run_time.snapshot(),
+        }
+    }
+}
+
+// see `ThreadPool::stats_handle`
+// keeps the counters alive, but not the workers, those still stop when the pool is dropped
+#[derive(Clone)]
+pub struct StatsHandle {
+    shared: Arc<Shared>,
+}
+
+impl StatsHandle {
+    pub fn stats(&self) -> Stats {
+        self.shared.stats()
+    }
+}
+
+// starts a thread for worker `id` and stores its handle in `slot`
+fn spawn_thread(id: usize, shared: Arc<Shared>, slot: ThreadSlot) -> io::Result<()> {
+    // holding the slot until the handle is stored means a new thread that dies right away
+    // can't store its own replacement first, only to have it overwritten by this older handle
+    let mut handle = lock(&slot);
+
+    let builder = (shared.thread_builder)(id);
+
+    // if the OS cannot create a thread due to limited system resources
+    // `thread::spawn` will panic
+    // `thread::Builder::spawn` returns a `Result` instead, so the error can be handled
+    let thread = builder.spawn({
+        let slot = Arc::clone(&slot);
+        move || run(id, shared, slot)
+    })?;
+
+    *handle = Some(thread);
+
+    Ok(())
+}
+
+fn run(id: usize, shared: Arc<Shared>, slot: ThreadSlot) {
+    CURRENT_WORKER.with(|current| current.set(Some((Arc::as_ptr(&shared), id))));
+
+    let sentinel = Sentinel { id, shared, slot };
+    let shared = &sentinel.shared;
+    let queue = &shared.queue;
+
+    let mut idle_since = Instant::now();
+
+    loop {
+        match queue.pop(id, || shared.idle_deadline(idle_since)) {
+            Some((job, queued_at)) => {
+                log::debug(format_args!("Worker {id} got a job; executing..."));
+
+                if let Some(message) = shared.run_job(job, queued_at) {
+                    log::error(format_args!("Worker {id} job panicked: {message}"));
+                }
+
+                idle_since = Instant::now();
+            }
+            None if queue.is_finished() => break,
+            None if queue.len() == 0 && shared.retire(idle_since.elapsed()) => {
+                // nobody joins a retired worker, dropping its handle detaches the thread
+                // and frees the slot for the next worker started
+                lock(&sentinel.slot).take();
+                log::debug(format_args!("Worker {id} not needed; shutting down..."));
+                return;
+            }
+            None => {}
+        }
+    }
+
+    log::debug(format_args!("Worker {id} disconnected; shutting down..."));
+}
+
+// runs `job`, returning its panic message if it panicked
+fn run_job(job: Job) -> Option<String> {
+    // `AssertUnwindSafe` is fine, the job is gone after this whatever happens,
+    // so nothing can see a half-finished job
+    panic::catch_unwind(AssertUnwindSafe(job))
+        .err()
+        .map(|payload| panic_message(&*payload))
+}
+
+// lives on the stack of every worker thread
+// if the thread unwinds anyway, say because a panic payload panicked when dropped,
+// its `drop` starts a replacement so the pool doesn't shrink
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+    slot: ThreadSlot,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        log::error(format_args!(
+            "Worker {} died, starting a replacement",
+            self.id
+        ));
+
+        if let Err(err) = spawn_thread(self.id, Arc::clone(&self.shared), Arc::clone(&self.slot)) {
+            log::error(format_args!("Couldn't replace worker {}: {err}", self.id));
+        }
+    }
+}
+
+// a panic while holding one of the pool's locks can't leave the data behind it inconsistent,
+// a deque or an `Option` is valid either way, so ignore the poisoning instead of spreading it
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// like `lock`, but returns `None` instead of waiting if the mutex is locked
+fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::channel,
+    };
+
+    #[test]
+    fn runs_jobs_on_named_threads() {
+        let pool = Builder::new(2).with_name("test-pool").build().unwrap();
+        let (sender, receiver) = channel();
+
+        for _ in 0..4 {
+            let sender = sender.clone();
+            pool.execute(move || {
+                let name = thread::current().name().map(String::from);
+                sender.send(name).unwrap();
+            });
+        }
+
+        drop(sender);
+        drop(pool);
+        // dropping the pool waits for every job to finish
+
+        let names: Vec<_> = receiver.iter().collect();
+        assert_eq!(names.len(), 4);
+        for name in names {
+            let name = name.unwrap();
+            assert!(
+                name == "test-pool-0" || name == "test-pool-1",
+                "unexpected thread name {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn zero_size_is_an_error() {
+        assert!(matches!(
+            ThreadPool::build(0),
+            Err(PoolCreationError::ZeroSize)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one worker")]
+    fn new_panics_on_zero_size() {
+        ThreadPool::new(0);
+    }
+
+    #[test]
+    fn stack_size_too_large_to_spawn() {
+        let result = Builder::new(2).with_stack_size(usize::MAX >> 1).build();
+
+        assert!(matches!(
+            result,
+            Err(PoolCreationError::Spawn { id: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn failed_spawn_shuts_down_started_workers() {
+        let (sender, receiver) = channel();
+
+        let sizing = Sizing {
+            min: 4,
+            max: 4,
+            idle_timeout: Duration::from_secs(60),
+        };
+
+        let name = String::from("partial");
+
+        let result = ThreadPool::build_with(sizing, name, None, Overflow::Block, move |id| {
+            let builder = thread::Builder::new().name(format!("partial-{id}"));
+            sender.send(id).unwrap();
+
+            // the OS can't give the third worker a stack this big
+            if id == 2 {
+                builder.stack_size(usize::MAX >> 1)
+            } else {
+                builder
+            }
+        });
+
+        assert!(matches!(
+            result,
+            Err(PoolCreationError::Spawn { id: 2, .. })
+        ));
+        // no fourth worker was attempted
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn submit_returns_results() {
+        let pool = ThreadPool::new(2);
+
+        let handles: Vec<_> = (0..8).map(|i| pool.submit(move || i * i)).collect();
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.wait().unwrap())
+            .collect();
+
+        assert_eq!(results, [0, 1, 4, 9, 16, 25, 36, 49]);
+    }
+
+    #[test]
+    fn panicking_job_is_an_error_and_worker_survives() {
+        let pool = ThreadPool::new(1);
+
+        let handle = pool.submit(|| -> u32 { panic!("boom {}", 42) });
+        assert_eq!(
+            handle.wait(),
+            Err(JobError::Panicked(String::from("boom 42")))
+        );
+
+        // the only worker is still there to run the next job
+        assert_eq!(pool.submit(|| "still alive").wait(), Ok("still alive"));
+    }
+
+    #[test]
+    fn try_wait_gives_the_handle_back() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = channel::<()>();
+
+        let handle = pool.submit(move || {
+            receiver.recv().unwrap();
+            String::from("done")
+        });
+
+        let handle = match handle.try_wait(Duration::from_millis(20)) {
+            Ok(result) => panic!("job finished early with {result:?}"),
+            Err(handle) => handle,
+        };
+
+        sender.send(()).unwrap();
+
+        match handle.try_wait(Duration::from_secs(5)) {
+            Ok(result) => assert_eq!(result.unwrap(), "done"),
+            Err(_) => panic!("job didn't finish"),
+        }
+    }
+
+    #[test]
+    fn dropped_handle_does_not_block_the_worker() {
+        let pool = ThreadPool::new(1);
+
+        drop(pool.submit(|| vec![0u8; 16]));
+
+        assert_eq!(pool.submit(|| 1 + 1).wait(), Ok(2));
+    }
+
+    // true if `size` jobs on `pool` can all be running at the same moment
+    fn runs_concurrently(pool: &ThreadPool, size: usize) -> bool {
+        let running = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..size)
+            .map(|_| {
+                let running = Arc::clone(&running);
+                pool.submit(move || {
+                    running.fetch_add(1, Ordering::SeqCst);
+
+                    let deadline = Instant::now() + Duration::from_secs(5);
+                    while running.load(Ordering::SeqCst) < size && Instant::now() < deadline {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+
+                    running.load(Ordering::SeqCst) == size
+                })
+            })
+            .collect();
+
+        handles.into_iter().all(|handle| handle.wait() == Ok(true))
+    }
+
+    #[test]
+    fn panicking_jobs_keep_the_pool_at_full_size() {
+        let pool = ThreadPool::new(3);
+
+        for i in 0..10 {
+            pool.execute(move || panic!("job {i} failed"));
+        }
+
+        assert_eq!(pool.size(), 3);
+        assert!(runs_concurrently(&pool, 3));
+    }
+
+    // a panic payload that panics again when it's dropped,
+    // which happens after `catch_unwind` has returned, so it kills the worker thread
+    struct PanicOnDrop;
+
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("payload dropped");
+        }
+    }
+
+    #[test]
+    fn dead_workers_are_replaced() {
+        let pool = Builder::new(2).with_name("respawn").build().unwrap();
+
+        for _ in 0..4 {
+            pool.execute(|| panic::panic_any(PanicOnDrop));
+        }
+
+        assert!(runs_concurrently(&pool, 2));
+
+        // the replacements got the same names as the threads they replaced
+        let name = pool
+            .submit(|| thread::current().name().map(String::from))
+            .wait()
+            .unwrap()
+            .unwrap();
+        assert!(
+            name.starts_with("respawn-"),
+            "unexpected thread name {name}"
+        );
+
+        drop(pool);
+        // shutting down joins the replacement threads too
+    }
+
+    #[test]
+    fn worker_dying_during_shutdown_is_replaced_and_joined() {
+        let pool = Builder::new(1).build().unwrap();
+        let (sender, receiver) = channel::<()>();
+
+        pool.execute(move || {
+            let _ = receiver.recv();
+            panic::panic_any(PanicOnDrop);
+        });
+
+        // the replacement needs the worker's slot while the pool is joining the dying thread
+        let (done, dropped) = channel();
+        thread::spawn(move || {
+            drop(pool);
+            done.send(()).unwrap();
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        drop(sender);
+        dropped.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn poisoned_lock_is_recovered() {
+        let mutex = Arc::new(Mutex::new(5));
+
+        let poisoner = Arc::clone(&mutex);
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(*lock(&mutex), 5);
+    }
+
+    // a pool with its only worker stuck on a job until the returned sender is used or dropped,
+    // and its one queue slot taken by the returned handle's job
+    pub(super) fn saturated_pool(
+        overflow: Overflow,
+    ) -> (ThreadPool, mpsc::Sender<()>, JobHandle<&'static str>) {
+        let pool = Builder::new(1)
+            .with_name("saturated")
+            .with_queue_capacity(1)
+            .with_overflow(overflow)
+            .build()
+            .unwrap();
+
+        let (started_sender, started) = channel();
+        let (release, released) = channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = released.recv();
+        });
+        started.recv().unwrap();
+
+        let queued = pool.submit(|| "queued");
+
+        (pool, release, queued)
+    }
+
+    #[test]
+    fn unbounded_by_default() {
+        let pool = ThreadPool::new(1);
+        let (release, released) = channel::<()>();
+        pool.execute(move || {
+            let _ = released.recv();
+        });
+
+        for _ in 0..1000 {
+            assert_eq!(pool.try_execute(|| {}), Ok(()));
+        }
+
+        drop(release);
+    }
+
+    #[test]
+    fn reject_when_full() {
+        let (pool, release, queued) = saturated_pool(Overflow::Reject);
+
+        assert_eq!(pool.try_execute(|| {}), Err(QueueFull));
+        assert_eq!(pool.stats().rejected, 1);
+
+        drop(release);
+        assert_eq!(queued.wait(), Ok("queued"));
+        // there's room again once the worker catches up
+        assert_eq!(pool.submit(|| "later").wait(), Ok("later"));
+    }
+
+    #[test]
+    fn drop_oldest_when_full() {
+        let (pool, release, queued) = saturated_pool(Overflow::DropOldest);
+
+        let newest = pool.submit(|| "newest");
+
+        drop(release);
+        assert_eq!(queued.wait(), Err(JobError::Lost));
+        assert_eq!(newest.wait(), Ok("newest"));
+    }
+
+    #[test]
+    fn caller_runs_when_full() {
+        let (pool, release, queued) = saturated_pool(Overflow::CallerRuns);
+
+        let caller = thread::current().id();
+        let ran_on = pool.submit(|| thread::current().id()).wait().unwrap();
+        assert_eq!(ran_on, caller);
+
+        drop(release);
+        assert_eq!(queued.wait(), Ok("queued"));
+    }
+
+    #[test]
+    fn block_until_there_is_room() {
+        let (pool, release, queued) = saturated_pool(Overflow::Block);
+        let pool = Arc::new(pool);
+
+        let (submitted_sender, submitted) = channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                let handle = pool.submit(|| "blocked");
+                submitted_sender.send(()).unwrap();
+                handle.wait()
+            })
+        };
+
+        assert!(submitted.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(release);
+        submitted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(queued.wait(), Ok("queued"));
+        assert_eq!(submitter.join().unwrap(), Ok("blocked"));
+    }
+
+    #[test]
+    fn idle_workers_steal_jobs_queued_on_a_busy_one() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let running = Arc::new(AtomicUsize::new(0));
+
+        // jobs submitted from a worker go to its own deque,
+        // so they can only all run at once if the other workers steal them
+        let inner = Arc::clone(&pool);
+        let spawner = pool.submit(move || {
+            (0..3)
+                .map(|_| {
+                    let running = Arc::clone(&running);
+                    inner.submit(move || {
+                        running.fetch_add(1, Ordering::SeqCst);
+                        let deadline = Instant::now() + Duration::from_secs(5);
+                        while running.load(Ordering::SeqCst) < 3 && Instant::now() < deadline {
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                        running.load(Ordering::SeqCst) == 3
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let handles = spawner.wait().unwrap();
+        for handle in handles {
+            assert_eq!(handle.wait(), Ok(true));
+        }
+    }
+
+    #[test]
+    fn many_tiny_jobs_from_many_threads() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let done = Arc::new(AtomicUsize::new(0));
+
+        let submitters: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = Arc::clone(&pool);
+                let done = Arc::clone(&done);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        let done = Arc::clone(&done);
+                        pool.execute(move || {
+                            done.fetch_add(1, Ordering::Relaxed);
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for submitter in submitters {
+            submitter.join().unwrap();
+        }
+
+        // dropping the last reference runs every queued job before the workers stop
+        Arc::into_inner(pool).unwrap();
+        assert_eq!(done.load(Ordering::Relaxed), 80_000);
+    }
+
+    #[test]
+    fn queued_counts_waiting_jobs() {
+        let (pool, release, queued) = saturated_pool(Overflow::Reject);
+        assert_eq!(pool.queued(), 1);
+
+        drop(release);
+        queued.wait().unwrap();
+        assert_eq!(pool.queued(), 0);
+    }
+
+    #[test]
+    fn builds_with_a_small_stack() {
+        let pool = Builder::new(1).with_stack_size(64 * 1024).build().unwrap();
+        let (sender, receiver) = channel();
+
+        pool.execute(move || sender.send(21 * 2).unwrap());
+
+        assert_eq!(receiver.recv().unwrap(), 42);
+    }
+
+    // polls `condition` for up to 5 seconds
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        true
+    }
+
+    #[test]
+    fn grows_while_jobs_wait_and_shrinks_when_idle() {
+        let pool = Builder::new(1)
+            .with_max_workers(3)
+            .with_idle_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert_eq!(pool.size(), 1);
+
+        assert!(runs_concurrently(&pool, 3));
+        assert_eq!(pool.size(), 3);
+
+        // never more than the maximum, however many jobs are waiting
+        let handles: Vec<_> = (0..20)
+            .map(|_| pool.submit(|| thread::sleep(Duration::from_millis(2))))
+            .collect();
+        assert_eq!(pool.size(), 3);
+        for handle in handles {
+            handle.wait().unwrap();
+        }
+
+        assert!(eventually(|| pool.size() == 1));
+        assert!(runs_concurrently(&pool, 1));
+    }
+
+    #[test]
+    fn shutdown_while_workers_retire() {
+        // a worker that has decided to retire still has to clear its slot,
+        // which mustn't wait on the pool joining that same worker
+        for _ in 0..200 {
+            let pool = Builder::new(1)
+                .with_max_workers(4)
+                .with_idle_timeout(Duration::ZERO)
+                .build()
+                .unwrap();
+
+            for _ in 0..8 {
+                pool.execute(|| {});
+            }
+
+            let started = Instant::now();
+            assert!(pool.join_timeout(Duration::from_secs(5)));
+            assert!(started.elapsed() < Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn full_queue_grows_the_pool_instead_of_overflowing() {
+        let pool = Builder::new(1)
+            .with_max_workers(2)
+            .with_queue_capacity(0)
+            .with_overflow(Overflow::Reject)
+            .build()
+            .unwrap();
+        let (sender, receiver) = channel::<()>();
+
+        // takes the only worker, which has been idle and waiting, so this fit without a queue
+        let blocker = pool.submit(move || receiver.recv().ok());
+        assert!(eventually(|| pool.queued() == 0));
+
+        // no room and nobody idle, so a second worker is started for this one
+        let (done, finished) = channel();
+        assert_eq!(pool.try_execute(move || done.send(()).unwrap()), Ok(()));
+        assert_eq!(pool.size(), 2);
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        drop(sender);
+        blocker.wait().unwrap();
+    }
+
+    #[test]
+    fn resize_starts_and_stops_workers() {
+        let pool = Builder::new(2).with_name("resized").build().unwrap();
+
+        pool.resize(4).unwrap();
+        assert_eq!(pool.size(), 4);
+        assert!(runs_concurrently(&pool, 4));
+
+        // surplus workers stop as soon as they're idle, without waiting for the idle timeout
+        pool.resize(1).unwrap();
+        assert!(eventually(|| pool.size() == 1));
+        assert!(runs_concurrently(&pool, 1));
+
+        // a stopped worker's slot, and so its id, is reused rather than counting up forever
+        pool.resize(2).unwrap();
+        let names: Vec<_> = (0..8)
+            .map(|_| pool.submit(|| thread::current().name().map(String::from)))
+            .map(|handle| handle.wait().unwrap().unwrap())
+            .collect();
+        let ids = ["resized-0", "resized-1", "resized-2", "resized-3"];
+        assert!(
+            names.iter().all(|name| ids.contains(&name.as_str())),
+            "unexpected thread names {names:?}"
+        );
+
+        assert!(matches!(pool.resize(0), Err(PoolCreationError::ZeroSize)));
+        assert_eq!(pool.size(), 2);
+    }
+
+    #[test]
+    fn resize_keeps_the_spread_between_size_and_maximum() {
+        let pool = Builder::new(1)
+            .with_max_workers(2)
+            .with_idle_timeout(Duration::from_secs(60))
+            .build()
+            .unwrap();
+
+        pool.resize(2).unwrap();
+        assert!(runs_concurrently(&pool, 3));
+        assert_eq!(pool.size(), 3);
+    }
+
+    #[test]
+    fn maximum_below_size_is_an_error() {
+        let result = Builder::new(4).with_max_workers(2).build();
+
+        assert!(matches!(
+            result,
+            Err(PoolCreationError::MaxBelowSize { size: 4, max: 2 })
+        ));
+    }
+
+    #[test]
+    fn stats_count_jobs_and_workers() {
+        let pool = Builder::new(2).with_max_workers(4).build().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!((stats.workers, stats.max_workers), (2, 4));
+        assert_eq!((stats.active, stats.queued, stats.completed), (0, 0, 0));
+
+        let (started_sender, started) = channel();
+        let (release, released) = channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = released.recv();
+        });
+        started.recv().unwrap();
+        assert_eq!(pool.stats().active, 1);
+
+        for i in 0..3 {
+            pool.execute(move || panic!("job {i} failed"));
+        }
+        for handle in (0..5).map(|i| pool.submit(move || i)).collect::<Vec<_>>() {
+            handle.wait().unwrap();
+        }
+
+        drop(release);
+        assert!(eventually(|| pool.stats().active == 0));
+
+        let stats = pool.stats();
+        assert_eq!(stats.completed, 6);
+        assert_eq!(stats.panicked, 3);
+        assert_eq!(stats.rejected, 0);
+        assert_eq!(stats.wait_time.count, 9);
+        assert_eq!(stats.run_time.count, 9);
+
+        // the handle outlives the pool's workers and keeps the final numbers
+        let handle = pool.stats_handle();
+        drop(pool);
+        assert_eq!(handle.stats().completed, 6);
+    }
+
+    // a pool with its one worker held by a job until `release` is used
+    fn blocked_pool() -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::new(1);
+
+        let (started_sender, started) = channel();
+        let (release, released) = channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = released.recv();
+        });
+        started.recv().unwrap();
+
+        (pool, release)
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let (pool, release) = blocked_pool();
+        let (sender, order) = channel();
+
+        for (priority, label) in [
+            (Priority::Low, "low"),
+            (Priority::Normal, "normal 1"),
+            (Priority::High, "high"),
+            (Priority::Normal, "normal 2"),
+        ] {
+            let sender = sender.clone();
+            pool.priority(priority)
+                .execute(move || sender.send(label).unwrap());
+        }
+        drop(sender);
+
+        drop(release);
+        assert_eq!(
+            order.iter().collect::<Vec<_>>(),
+            ["high", "normal 1", "normal 2", "low"]
+        );
+    }
+
+    #[test]
+    fn drop_oldest_drops_the_lowest_priority_first() {
+        let (pool, release, queued) = saturated_pool(Overflow::DropOldest);
+
+        let low = pool.priority(Priority::Low).submit(|| "low");
+        let high = pool.priority(Priority::High).submit(|| "high");
+
+        drop(release);
+        assert_eq!(queued.wait(), Err(JobError::Lost));
+        assert_eq!(low.wait(), Err(JobError::Lost));
+        assert_eq!(high.wait(), Ok("high"));
+    }
+
+    #[test]
+    fn delayed_jobs_run_after_their_delay() {
+        let pool = Builder::new(1).with_name("delayed").build().unwrap();
+        let (sender, ran) = channel();
+
+        let scheduled = Instant::now();
+        pool.execute_after(Duration::from_millis(50), move || {
+            sender
+                .send(thread::current().name().map(String::from))
+                .unwrap();
+        });
+
+        let name = ran.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(scheduled.elapsed() >= Duration::from_millis(50));
+        // run by a worker, not by the timer thread
+        assert_eq!(name.as_deref(), Some("delayed-0"));
+    }
+
+    #[test]
+    fn delayed_jobs_run_in_order_of_their_time() {
+        let pool = ThreadPool::new(1);
+        let (sender, order) = channel();
+
+        for (millis, label) in [(60, "third"), (20, "first"), (40, "second")] {
+            let sender = sender.clone();
+            pool.execute_after(Duration::from_millis(millis), move || {
+                sender.send(label).unwrap();
+            });
+        }
+        drop(sender);
+
+        let order: Vec<_> = order.iter().take(3).collect();
+        assert_eq!(order, ["first", "second", "third"]);
+    }
+
+    #[test]
+    fn cancelled_jobs_never_run() {
+        let pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&ran);
+        let token = pool.execute_after(Duration::from_millis(30), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+
+        // a later job shows the timer has gone past the cancelled one
+        let (sender, later) = channel();
+        pool.execute_after(Duration::from_millis(60), move || sender.send(()).unwrap());
+        later.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn periodic_jobs_repeat_until_cancelled() {
+        let pool = ThreadPool::new(2);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&runs);
+        let token = pool.execute_every(Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert!(eventually(|| runs.load(Ordering::SeqCst) >= 3));
+        token.cancel();
+
+        // a run queued just before the cancel may still happen, but none after that
+        thread::sleep(Duration::from_millis(30));
+        let stopped_at = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(Ordering::SeqCst), stopped_at);
+    }
+
+    #[test]
+    fn panicking_periodic_job_keeps_running() {
+        let pool = ThreadPool::new(1);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&runs);
+        let token = pool.execute_every(Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            panic!("periodic job failed");
+        });
+
+        assert!(eventually(|| runs.load(Ordering::SeqCst) >= 3));
+        token.cancel();
+        assert!(eventually(|| pool.stats().panicked >= 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "interval above zero")]
+    fn zero_interval_panics() {
+        ThreadPool::new(1).execute_every(Duration::ZERO, || {});
+    }
+
+    #[test]
+    fn pending_delayed_jobs_are_dropped_at_shutdown() {
+        let pool = Builder::new(1).with_name("shutdown").build().unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&ran);
+        pool.execute_after(Duration::from_secs(60), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // doesn't wait the minute for the job
+        let started = Instant::now();
+        drop(pool);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+        // the job and what it captured are gone too
+        assert_eq!(Arc::strong_count(&ran), 1);
+    }
+}
@@ -0,0 +1,194 @@
+use super::ThreadPool;
+
+// how many chunks each worker gets when the caller doesn't pick a chunk size
+// more than one, so a worker that finishes early can take over from one that's slower
+const CHUNKS_PER_WORKER: usize = 4;
+
+impl ThreadPool {
+    // calls `f` on every chunk of `chunk_size` items, spread over the workers,
+    // and returns what it gave back for each chunk, in the order of the chunks
+    // ```
+    // let lines: Vec<&str> = contents.lines().collect();
+    // let counts = pool.par_chunks(&lines, 1000, |lines| {
+    //     lines.iter().filter(|line| line.contains(query)).count()
+    // });
+    // ```
+    // the last chunk is shorter if the items don't divide evenly, like with `slice::chunks`
+    // panics if `chunk_size` is zero, or once every chunk is done if `f` panicked on any of them
+    pub fn par_chunks<T, R, F>(&self, items: &[T], chunk_size: usize, f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&[T]) -> R + Sync,
+    {
+        assert!(chunk_size > 0, "par_chunks needs a chunk size above zero");
+
+        let mut results: Vec<Option<R>> = items.chunks(chunk_size).map(|_| None).collect();
+
+        self.scope(|s| {
+            let f = &f;
+
+            for (chunk, result) in items.chunks(chunk_size).zip(&mut results) {
+                s.spawn(move || *result = Some(f(chunk)));
+            }
+        });
+
+        // `scope` only returns once every chunk has stored its result, it panics otherwise
+        results
+            .into_iter()
+            .map(|result| result.expect("every chunk has a result"))
+            .collect()
+    }
+
+    // `items.iter().map(f).collect()`, but spread over the workers
+    // the results are in the same order as the items
+    pub fn par_map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        self.par_chunks(items, self.chunk_size(items.len()), |chunk| {
+            chunk.iter().map(&f).collect::<Vec<_>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // calls `f` on every item, spread over the workers, and returns once it's been called on all of them
+    pub fn par_for_each<T, F>(&self, items: &[T], f: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync,
+    {
+        self.par_chunks(items, self.chunk_size(items.len()), |chunk| {
+            chunk.iter().for_each(&f);
+        });
+    }
+
+    // splits `len` items into a few chunks per worker, but never chunks of nothing
+    fn chunk_size(&self, len: usize) -> usize {
+        len.div_ceil(self.size() * CHUNKS_PER_WORKER).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::{Builder, Overflow};
+    use std::{
+        collections::HashSet,
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    #[test]
+    fn map_keeps_the_order_of_the_items() {
+        let pool = ThreadPool::new(4);
+        let numbers: Vec<u64> = (0..1000).collect();
+
+        let squares = pool.par_map(&numbers, |n| n * n);
+
+        assert_eq!(squares, numbers.iter().map(|n| n * n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn chunks_are_never_dropped_by_the_overflow_policy() {
+        let pool = Builder::new(1)
+            .with_queue_capacity(1)
+            .with_overflow(Overflow::DropOldest)
+            .build()
+            .unwrap();
+        let numbers: Vec<usize> = (0..64).collect();
+        let count = AtomicUsize::new(0);
+
+        pool.par_for_each(&numbers, |_| {
+            count.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 64);
+    }
+
+    #[test]
+    fn chunks_come_back_in_order() {
+        let pool = ThreadPool::new(3);
+        let numbers: Vec<u32> = (1..=10).collect();
+
+        let sums = pool.par_chunks(&numbers, 3, |chunk| chunk.iter().sum::<u32>());
+
+        assert_eq!(sums, [6, 15, 24, 10]);
+    }
+
+    #[test]
+    fn searches_lines_in_parallel() {
+        let pool = ThreadPool::new(2);
+        let contents = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.";
+        let lines: Vec<&str> = contents.lines().collect();
+
+        let matches: Vec<&str> = pool
+            .par_chunks(&lines, 1, |lines| {
+                lines
+                    .iter()
+                    .copied()
+                    .filter(|line| line.to_lowercase().contains("rust"))
+                    .collect::<Vec<_>>()
+            })
+            .concat();
+
+        assert_eq!(matches, ["Rust:", "Trust me."]);
+    }
+
+    #[test]
+    fn for_each_visits_every_item_on_the_workers() {
+        let pool = ThreadPool::new(4);
+        let items: Vec<usize> = (0..100).collect();
+        let total = AtomicUsize::new(0);
+        let threads = Mutex::new(HashSet::new());
+
+        pool.par_for_each(&items, |n| {
+            total.fetch_add(*n, Ordering::SeqCst);
+            threads.lock().unwrap().insert(thread::current().id());
+        });
+
+        assert_eq!(total.load(Ordering::SeqCst), 4950);
+        assert!(!threads.lock().unwrap().contains(&thread::current().id()));
+    }
+
+    #[test]
+    fn nothing_to_do() {
+        let pool = ThreadPool::new(2);
+
+        assert_eq!(pool.par_map(&[] as &[u8], |n| *n), []);
+        assert!(pool.par_chunks(&[] as &[u8], 4, <[u8]>::len).is_empty());
+    }
+
+    #[test]
+    fn panics_once_every_chunk_is_done() {
+        let pool = ThreadPool::new(2);
+        let items: Vec<usize> = (0..8).collect();
+        let done = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.par_chunks(&items, 1, |chunk| {
+                if chunk[0] == 3 {
+                    panic!("chunk failed");
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(done.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk size above zero")]
+    fn zero_chunk_size_panics() {
+        ThreadPool::new(1).par_chunks(&[1, 2, 3], 0, <[i32]>::len);
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::VecDeque,
+    hint,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use super::{lock, try_lock, Job, Priority};
+
+// a job, and when it was queued
+pub(super) type Queued = (Job, Instant);
+
+// a worker's jobs, one deque for each priority, all behind the worker's one lock
+type Deques = [VecDeque<Queued>; Priority::COUNT];
+
+// how many times an idle worker looks for a job before it goes to sleep
+const SPIN_ROUNDS: usize = 64;
+
+// the jobs waiting for a worker, split into one deque per worker
+// with a single shared queue every worker has to take the same lock to get its next job,
+// here each worker mostly takes jobs from its own deque and only touches another one to steal
+// ```
+// worker 0: [a, b, c]    pops `a` from the front
+// worker 1: []           idle, so steals `b` from worker 0's deque
+// ```
+// a pool that has grown past the workers it was built with shares the deques between them
+// every worker looks for the highest priority job first, in its own deque and then in the others
+pub(super) struct Queue {
+    deques: Vec<Mutex<Deques>>,
+    // jobs sitting in any deque, or about to be pushed into one
+    // kept separately so nobody has to lock every deque to find out if there's work
+    queued: AtomicUsize,
+    // jobs sitting in the deques at each priority, so an empty one is skipped without locking anything
+    waiting: [AtomicUsize; Priority::COUNT],
+    // `None` takes as many jobs as are pushed
+    capacity: Option<usize>,
+    // jobs pushed from outside the pool go to the deques in turn
+    next: AtomicUsize,
+    // workers waiting on `work`, and pushers waiting on `room`
+    sleepers: AtomicUsize,
+    blocked: AtomicUsize,
+    closed: AtomicBool,
+    // the condvars need a mutex, but all the state they guard is in the atomics above
+    sleep: Mutex<()>,
+    work: Condvar,
+    room: Condvar,
+}
+
+// sleeping and waking relies on the pairs of atomics being `SeqCst`:
+// a worker going to sleep bumps `sleepers` and then checks `queued`,
+// a pusher bumps `queued` and then checks `sleepers`,
+// so at least one of them sees the other, and either the worker stays awake or the pusher wakes it
+// the same goes for `blocked` and `queued` between a waiting pusher and a worker taking a job
+
+impl Queue {
+    pub(super) fn new(workers: usize, capacity: Option<usize>) -> Self {
+        Self {
+            deques: (0..workers).map(|_| Mutex::default()).collect(),
+            queued: AtomicUsize::new(0),
+            waiting: Default::default(),
+            capacity,
+            next: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            sleep: Mutex::new(()),
+            work: Condvar::new(),
+            room: Condvar::new(),
+        }
+    }
+
+    // the number of jobs waiting for a worker
+    pub(super) fn len(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    // the number of workers waiting for a job
+    pub(super) fn sleepers(&self) -> usize {
+        self.sleepers.load(Ordering::SeqCst)
+    }
+
+    // closed, and every job has been taken
+    pub(super) fn is_finished(&self) -> bool {
+        self.closed.load(Ordering::SeqCst) && self.len() == 0
+    }
+
+    // claims a place for one job, which must then be handed to `push`
+    // idle workers count as room too, a job for them doesn't wait at all
+    pub(super) fn try_reserve(&self) -> bool {
+        let Some(capacity) = self.capacity else {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+            return true;
+        };
+
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                let room = capacity + self.sleepers.load(Ordering::SeqCst);
+                (queued < room).then_some(queued + 1)
+            })
+            .is_ok()
+    }
+
+    // claims a place even if the queue is full, for a job a worker that was just started will take
+    pub(super) fn reserve_anyway(&self) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // like `try_reserve`, but waits for room instead of giving up
+    pub(super) fn reserve_blocking(&self) {
+        self.blocked.fetch_add(1, Ordering::SeqCst);
+
+        let mut guard = lock(&self.sleep);
+        while !self.try_reserve() {
+            guard = self.room.wait(guard).unwrap_or_else(|p| p.into_inner());
+        }
+        drop(guard);
+
+        self.blocked.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // adds a job whose place was reserved, to the deque of `worker` if given
+    // a worker pushing to its own deque keeps the job on the thread that made it, while its data is still in cache
+    pub(super) fn push(&self, job: Job, priority: Priority, worker: Option<usize>) {
+        let index = worker.unwrap_or_else(|| self.next.fetch_add(1, Ordering::Relaxed));
+
+        let mut deques = lock(&self.deques[index % self.deques.len()]);
+        deques[priority.index()].push_back((job, Instant::now()));
+        self.waiting[priority.index()].fetch_add(1, Ordering::SeqCst);
+        drop(deques);
+
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _guard = lock(&self.sleep);
+            self.work.notify_one();
+        }
+    }
+
+    // waits for a job for worker `id`, until the time given by `deadline` if it gives one
+    // returns `None` once the queue is closed and every job has been taken, once the deadline has passed,
+    // and when woken up without finding a job, so the worker can check whether it's still needed
+    // `deadline` is asked right before going to sleep, with the lock `wake_all` takes held,
+    // so whatever changed it can't wake everyone in between and leave this worker sleeping without a deadline
+    pub(super) fn pop(&self, id: usize, deadline: impl Fn() -> Option<Instant>) -> Option<Queued> {
+        let mut woken = false;
+
+        loop {
+            // going to sleep and being woken costs far more than a tiny job,
+            // so look around for a little while first
+            for round in 0..SPIN_ROUNDS {
+                if let Some(job) = self.take(id) {
+                    return Some(job);
+                }
+
+                if round < SPIN_ROUNDS / 2 {
+                    hint::spin_loop();
+                } else {
+                    thread::yield_now();
+                }
+            }
+
+            if woken {
+                return None;
+            }
+
+            let guard = lock(&self.sleep);
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+
+            if self.queued.load(Ordering::SeqCst) > 0 {
+                // a job is on its way, or sits in a deque we just looked at too early
+                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                drop(guard);
+                thread::yield_now();
+                continue;
+            }
+
+            if self.closed.load(Ordering::SeqCst) {
+                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+
+            // an idle worker is room for one more job
+            if self.blocked.load(Ordering::SeqCst) > 0 {
+                self.room.notify_all();
+            }
+
+            let guard = match deadline() {
+                None => self.work.wait(guard).unwrap_or_else(|p| p.into_inner()),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+
+                    if timeout.is_zero() {
+                        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                        return None;
+                    }
+
+                    match self.work.wait_timeout(guard, timeout) {
+                        Ok((guard, _)) => guard,
+                        Err(poisoned) => poisoned.into_inner().0,
+                    }
+                }
+            };
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+            drop(guard);
+
+            woken = true;
+        }
+    }
+
+    // takes the oldest job of the highest priority there is,
+    // from this worker's own deque, or else by stealing from another
+    // stealing in order too means jobs left in the deque of a worker that has stopped aren't overtaken
+    // unlike `pop`, returns `None` right away if there's nothing to take
+    pub(super) fn take(&self, id: usize) -> Option<Queued> {
+        let count = self.deques.len();
+
+        let job = (0..Priority::COUNT)
+            .filter(|&priority| self.waiting[priority].load(Ordering::SeqCst) > 0)
+            .find_map(|priority| {
+                // a `let` of its own, so the guard is dropped before stealing
+                // and the own deque isn't kept locked while the others are searched
+                let own = self.pop_front(&mut lock(&self.deques[id % count]), priority);
+
+                own.or_else(|| {
+                    (1..count).find_map(|offset| {
+                        // a deque that's locked is being worked on already, so try the next one
+                        let mut deques = try_lock(&self.deques[(id + offset) % count])?;
+                        self.pop_front(&mut deques, priority)
+                    })
+                })
+            })?;
+
+        self.taken();
+        Some(job)
+    }
+
+    // removes the job that has waited the longest at the lowest priority there is,
+    // as far as one can tell without locking every deque at once
+    pub(super) fn take_oldest(&self) -> Option<Job> {
+        let count = self.deques.len();
+        let start = self.next.load(Ordering::Relaxed);
+
+        let (job, _) = (0..Priority::COUNT).rev().find_map(|priority| {
+            (0..count).find_map(|offset| {
+                self.pop_front(&mut lock(&self.deques[(start + offset) % count]), priority)
+            })
+        })?;
+
+        self.taken();
+        Some(job)
+    }
+
+    fn pop_front(&self, deques: &mut Deques, priority: usize) -> Option<Queued> {
+        let job = deques[priority].pop_front()?;
+        self.waiting[priority].fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    fn taken(&self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            let _guard = lock(&self.sleep);
+            self.room.notify_all();
+        }
+    }
+
+    // lets the workers finish the jobs already queued, then makes `pop` return `None`
+    pub(super) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.wake_all();
+    }
+
+    // makes every sleeping worker's `pop` return
+    pub(super) fn wake_all(&self) {
+        let _guard = lock(&self.sleep);
+        self.work.notify_all();
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{lock, Job, Priority, ThreadPool};
+use crate::log;
+
+// lets jobs borrow from the stack of whoever started them, see `ThreadPool::scope`
+// `'scope` is how long the scope lasts, and `'env` anything borrowed from outside it,
+// the same as `std::thread::Scope`
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    // invariant in both lifetimes, so neither can be shortened to let a borrow slip out of the scope
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+// shared between a scope and its jobs, which may outlive the `Scope` itself by a moment
+struct ScopeState {
+    // jobs spawned that haven't finished or been thrown away yet, and how many of them panicked
+    jobs: Mutex<Jobs>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct Jobs {
+    pending: usize,
+    panicked: usize,
+}
+
+impl ThreadPool {
+    // runs `f`, whose jobs can borrow anything that outlives the call to `scope`
+    // ```
+    // let mut lines = vec![String::new(); 4];
+    // pool.scope(|s| {
+    //     for (i, line) in lines.iter_mut().enumerate() {
+    //         s.spawn(move || *line = format!("line {i}"));
+    //     }
+    // });
+    // // every job has finished by now, so `lines` can be used again
+    // ```
+    // doesn't return until every job spawned in the scope has finished, even if `f` panics
+    // if any of the jobs panicked, `scope` panics too, once they're all done
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                jobs: Mutex::new(Jobs::default()),
+                finished: Condvar::new(),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+
+        // the jobs borrow what `f` could, returning or unwinding before they're done would leave them dangling
+        scope.wait();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.panicked() > 0 => {
+                panic!("{} scoped job(s) panicked", scope.panicked())
+            }
+            Ok(value) => value,
+        }
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    // runs `f` on the pool, and makes the scope wait for it
+    // jobs can spawn more jobs into the same scope through the `&Scope` they borrowed
+    // a job the pool has no room for runs on this thread instead, whatever the overflow policy,
+    // since the scope couldn't return without it
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        lock(&self.state.jobs).pending += 1;
+
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(f);
+        // SAFETY: only the lifetime changes, and the job can't outlive it anyway:
+        // `ThreadPool::scope` doesn't return until `ScopedJob`'s `drop` has counted this job as finished,
+        // and that happens only after the job, with everything it borrowed, has run or been dropped
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+
+        let scoped = ScopedJob {
+            job: Some(job),
+            state: Arc::clone(&self.state),
+        };
+
+        if let Err(job) = self
+            .pool
+            .try_schedule(Box::new(move || scoped.run()), Priority::Normal)
+        {
+            if let Some(message) = self.pool.shared.run_job(job, Instant::now()) {
+                log::error(format_args!("Scoped job panicked on the caller: {message}"));
+            }
+        }
+    }
+
+    // blocks until every job spawned in the scope has finished
+    fn wait(&self) {
+        let state = &self.state;
+
+        // a worker of this pool waiting for its own pool's jobs could wait forever,
+        // say if it's the only worker, so it runs queued jobs itself in the meantime
+        let Some(id) = self.pool.current_worker() else {
+            let mut jobs = lock(&state.jobs);
+            while jobs.pending > 0 {
+                jobs = state.finished.wait(jobs).unwrap_or_else(|p| p.into_inner());
+            }
+            return;
+        };
+
+        let shared = &self.pool.shared;
+
+        while lock(&state.jobs).pending > 0 {
+            if let Some((job, queued_at)) = shared.queue.take(id) {
+                if let Some(message) = shared.run_job(job, queued_at) {
+                    log::error(format_args!("Worker {id} job panicked: {message}"));
+                }
+                continue;
+            }
+
+            // the scope's last jobs are running on other workers, check back shortly
+            let jobs = lock(&state.jobs);
+            if jobs.pending > 0 {
+                let _ = state.finished.wait_timeout(jobs, Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn panicked(&self) -> usize {
+        lock(&self.state.jobs).panicked
+    }
+}
+
+// a scoped job as it's handed to the pool
+// it counts as finished when it's dropped, once it has run or panicked
+struct ScopedJob {
+    job: Option<Job>,
+    state: Arc<ScopeState>,
+}
+
+impl ScopedJob {
+    fn run(mut self) {
+        if let Some(job) = self.job.take() {
+            job();
+        }
+    }
+}
+
+impl Drop for ScopedJob {
+    fn drop(&mut self) {
+        // a job still here was taken out of the queue by another job's `DropOldest` overflow,
+        // run it on this thread instead of losing it, the scope may depend on what it does
+        let panicked = match self.job.take() {
+            Some(job) => panic::catch_unwind(AssertUnwindSafe(job)).is_err(),
+            // a job that panics is dropped while its thread unwinds
+            None => thread::panicking(),
+        };
+
+        let mut jobs = lock(&self.state.jobs);
+        jobs.pending -= 1;
+        if panicked {
+            jobs.panicked += 1;
+        }
+
+        if jobs.pending == 0 {
+            self.state.finished.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::{tests::saturated_pool, Builder, Overflow};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    };
+
+    #[test]
+    fn jobs_borrow_from_the_stack() {
+        let pool = ThreadPool::new(4);
+        let mut numbers: Vec<u64> = (1..=100).collect();
+        let total = AtomicUsize::new(0);
+
+        pool.scope(|s| {
+            for chunk in numbers.chunks_mut(10) {
+                let total = &total;
+                s.spawn(move || {
+                    for n in chunk {
+                        *n *= 2;
+                        total.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(numbers.iter().sum::<u64>(), 2 * 5050);
+        assert_eq!(total.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn returns_once_every_job_has_finished() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+
+        let value = pool.scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(10));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            "done"
+        });
+
+        assert_eq!(value, "done");
+        assert_eq!(finished.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn jobs_spawn_more_jobs() {
+        let pool = ThreadPool::new(2);
+        let count = AtomicUsize::new(0);
+
+        pool.scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    s.spawn(|| {
+                        count.fetch_add(1, Ordering::SeqCst);
+                    });
+                });
+            }
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn panicking_job_panics_the_scope_after_the_rest_finish() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| panic!("scoped job failed"));
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        thread::sleep(Duration::from_millis(10));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+        // and the pool carries on
+        assert_eq!(pool.submit(|| 1).wait(), Ok(1));
+    }
+
+    #[test]
+    fn waits_for_jobs_even_when_the_closure_panics() {
+        let pool = ThreadPool::new(1);
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+                panic!("scope body failed");
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn scope_on_a_worker_runs_its_own_jobs() {
+        // with one worker busy waiting for the scope, nobody else could run its jobs
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner = Arc::clone(&pool);
+
+        let sum = pool.submit(move || {
+            let mut parts = [0; 4];
+            inner.scope(|s| {
+                for (i, part) in parts.iter_mut().enumerate() {
+                    s.spawn(move || *part = i + 1);
+                }
+            });
+            parts.iter().sum::<usize>()
+        });
+
+        assert_eq!(sum.try_wait(Duration::from_secs(5)).ok(), Some(Ok(10)));
+    }
+
+    #[test]
+    fn refused_jobs_run_on_the_caller() {
+        let (pool, release, queued) = saturated_pool(Overflow::Reject);
+
+        let caller = thread::current().id();
+        let mut ran_on = None;
+        pool.scope(|s| s.spawn(|| ran_on = Some(thread::current().id())));
+
+        assert_eq!(ran_on, Some(caller));
+        drop(release);
+        queued.wait().unwrap();
+    }
+
+    #[test]
+    fn jobs_dropped_by_the_overflow_policy_still_run() {
+        let pool = Builder::new(1)
+            .with_queue_capacity(1)
+            .with_overflow(Overflow::DropOldest)
+            .build()
+            .unwrap();
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = released.recv();
+        });
+        started.recv().unwrap();
+
+        let mut ran = false;
+        pool.scope(|s| {
+            // takes the only place in the queue
+            s.spawn(|| ran = true);
+            // and is thrown out again to make room for this one
+            pool.execute(|| {});
+        });
+
+        assert!(ran);
+        drop(release);
+    }
+}
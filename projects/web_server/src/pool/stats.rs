@@ -0,0 +1,171 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+// a snapshot of what a `ThreadPool` is doing, from `ThreadPool::stats`
+// the numbers are read one at a time while the pool keeps running,
+// so they don't have to add up exactly, say `queued` against `wait_time.count`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    // worker threads running, and how many the pool may grow to
+    pub workers: usize,
+    pub max_workers: usize,
+    // workers running a job right now
+    pub active: usize,
+    // jobs waiting for a worker
+    pub queued: usize,
+    // jobs that finished, and jobs that panicked instead
+    pub completed: u64,
+    pub panicked: u64,
+    // jobs refused or thrown away because the queue was full
+    pub rejected: u64,
+    // how long jobs waited in the queue before a worker started them
+    pub wait_time: Histogram,
+    // how long jobs ran for, panicked ones included
+    pub run_time: Histogram,
+}
+
+// durations sorted into buckets, the way Prometheus histograms count them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    // every upper bound with the number of durations up to it, so the counts only go up
+    // durations above the last bound are only in `count`
+    pub buckets: Vec<(Duration, u64)>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl Histogram {
+    pub fn mean(&self) -> Option<Duration> {
+        let mean = self.sum.as_nanos().checked_div(self.count.into())?;
+        Some(Duration::from_nanos(mean as u64))
+    }
+}
+
+// upper bounds of the histogram buckets, from 100µs for a job picked up right away,
+// to 10s for one stuck behind slow requests
+const BUCKETS: [Duration; 16] = [
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+// the counters behind `Stats`, updated by the workers as they go
+// the gauges, workers and queued jobs, are read straight from the pool instead
+#[derive(Default)]
+pub(super) struct Metrics {
+    pub(super) active: AtomicUsize,
+    pub(super) completed: AtomicU64,
+    pub(super) panicked: AtomicU64,
+    pub(super) rejected: AtomicU64,
+    pub(super) wait_time: Recorder,
+    pub(super) run_time: Recorder,
+}
+
+impl Metrics {
+    // counts a worker as active until the returned guard is dropped,
+    // which happens even if the thread unwinds out of the job
+    pub(super) fn start_job(&self) -> Active<'_> {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Active(&self.active)
+    }
+}
+
+pub(super) struct Active<'a>(&'a AtomicUsize);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        // pairs with the `Acquire` load in `stats`, which then sees every count made before this
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+// a `Histogram` that can be added to from any thread
+#[derive(Default)]
+pub(super) struct Recorder {
+    // one more than `BUCKETS`, for everything above the last bound
+    // each duration is only counted in its own bucket, `snapshot` adds them up
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Recorder {
+    pub(super) fn record(&self, duration: Duration) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(BUCKETS.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        // a u64 of nanoseconds lasts for 584 years of job time
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn snapshot(&self) -> Histogram {
+        let mut count = 0;
+        let mut buckets = Vec::with_capacity(BUCKETS.len());
+
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            count += bucket.load(Ordering::Relaxed);
+            buckets.push((*bound, count));
+        }
+        count += self.buckets[BUCKETS.len()].load(Ordering::Relaxed);
+
+        Histogram {
+            buckets,
+            count,
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_cumulative() {
+        let recorder = Recorder::default();
+
+        for micros in [50, 100, 300, 2_000, 20_000_000] {
+            recorder.record(Duration::from_micros(micros));
+        }
+
+        let histogram = recorder.snapshot();
+
+        assert_eq!(histogram.count, 5);
+        assert_eq!(histogram.sum, Duration::from_micros(20_002_450));
+        assert_eq!(histogram.buckets[0], (Duration::from_micros(100), 2));
+        assert_eq!(histogram.buckets[1], (Duration::from_micros(250), 2));
+        assert_eq!(histogram.buckets[2], (Duration::from_micros(500), 3));
+        assert_eq!(histogram.buckets[4], (Duration::from_micros(2500), 4));
+        // 20s is past the last bound, only `count` has it
+        assert_eq!(
+            histogram.buckets.last(),
+            Some(&(Duration::from_secs(10), 4))
+        );
+    }
+
+    #[test]
+    fn mean_of_nothing() {
+        let histogram = Recorder::default().snapshot();
+
+        assert_eq!(histogram.mean(), None);
+        assert!(histogram.buckets.iter().all(|(_, count)| *count == 0));
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    io, mem,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{lock, Job, Priority};
+
+// stops a job started with `execute_after` or `execute_every`
+// cheap to clone, and every clone stops the same job
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    // a delayed job that hasn't been queued yet never will be, and a periodic job isn't run again
+    // a run that has already been queued or started still happens
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+// what to do once an entry is due
+pub(super) enum Task {
+    Once(Job),
+    // runs `f`, and then adds it again for `interval` after the time it was due
+    Every {
+        f: Arc<dyn Fn() + Send + Sync>,
+        interval: Duration,
+    },
+}
+
+pub(super) struct Entry {
+    pub(super) due: Instant,
+    // entries due at the same moment come out in the order they were added
+    seq: u64,
+    pub(super) priority: Priority,
+    pub(super) token: CancellationToken,
+    pub(super) task: Task,
+}
+
+// only `due` and `seq` matter for the order, the job in `task` can't be compared anyway
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+// the jobs waiting for their time to come
+// a thread of its own waits for the soonest one, and hands each to the queue when it's due
+// it's only started once something is scheduled, most pools never need it
+#[derive(Default)]
+pub(super) struct Timer {
+    state: Mutex<TimerState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    // soonest first
+    entries: BinaryHeap<Reverse<Entry>>,
+    next_seq: u64,
+    closed: bool,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Timer {
+    // adds a job to run at `due`, using `start` to start the timer thread if it isn't running yet
+    // does nothing once the timer is closed
+    pub(super) fn add(
+        &self,
+        due: Instant,
+        priority: Priority,
+        token: CancellationToken,
+        task: Task,
+        start: impl FnOnce() -> io::Result<thread::JoinHandle<()>>,
+    ) -> io::Result<()> {
+        let mut state = lock(&self.state);
+
+        if state.closed {
+            return Ok(());
+        }
+
+        if state.thread.is_none() {
+            state.thread = Some(start()?);
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+
+        state.entries.push(Reverse(Entry {
+            due,
+            seq,
+            priority,
+            token,
+            task,
+        }));
+
+        // the new entry may be due sooner than the one the timer thread is waiting for
+        self.changed.notify_one();
+
+        Ok(())
+    }
+
+    // waits until the soonest entry is due and returns it, or `None` once the timer is closed
+    pub(super) fn next(&self) -> Option<Entry> {
+        let mut state = lock(&self.state);
+
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            let now = Instant::now();
+
+            state = match state.entries.peek().map(|Reverse(entry)| entry.due) {
+                Some(due) if due <= now => return state.entries.pop().map(|Reverse(entry)| entry),
+                Some(due) => match self.changed.wait_timeout(state, due - now) {
+                    Ok((state, _)) => state,
+                    Err(poisoned) => poisoned.into_inner().0,
+                },
+                None => self.changed.wait(state).unwrap_or_else(|p| p.into_inner()),
+            };
+        }
+    }
+
+    // stops the timer thread, and drops every job that wasn't due yet
+    pub(super) fn close(&self) {
+        let (entries, thread) = {
+            let mut state = lock(&self.state);
+            state.closed = true;
+            (mem::take(&mut state.entries), state.thread.take())
+        };
+        self.changed.notify_all();
+
+        // dropped without the lock, a job's captures could have anything in their `drop`
+        drop(entries);
+
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+}
+
+// when a periodic job is due next, the first tick after now
+// ticks a slow run made it miss are skipped rather than run back to back to catch up
+pub(super) fn next_due(due: Instant, interval: Duration) -> Instant {
+    let next = due + interval;
+    let now = Instant::now();
+
+    if next > now {
+        return next;
+    }
+
+    let missed = (now - next).as_nanos() / interval.as_nanos();
+    next + interval.saturating_mul(u32::try_from(missed + 1).unwrap_or(u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missed_ticks_are_skipped() {
+        let interval = Duration::from_millis(100);
+
+        let due = Instant::now();
+        assert_eq!(next_due(due, interval), due + interval);
+
+        // due 350ms ago, so the ticks at -250, -150 and -50ms are gone and the next is at +50ms
+        let due = Instant::now() - Duration::from_millis(350);
+        assert_eq!(next_due(due, interval), due + 4 * interval);
+    }
+
+    #[test]
+    fn entries_come_out_soonest_first() {
+        let timer = Timer::default();
+        // all of them due already, so `next` doesn't wait
+        let start = Instant::now() - Duration::from_millis(100);
+
+        for offset in [20, 0, 0, 10] {
+            let task = Task::Once(Box::new(|| {}));
+            let due = start + Duration::from_millis(offset);
+
+            timer
+                .add(
+                    due,
+                    Priority::Normal,
+                    CancellationToken::default(),
+                    task,
+                    || {
+                        // a thread that's done already, the test plays the timer thread itself
+                        Ok(thread::spawn(|| {}))
+                    },
+                )
+                .unwrap();
+        }
+
+        let order: Vec<_> = (0..4)
+            .map(|_| timer.next().unwrap())
+            .map(|entry| (entry.due - start, entry.seq))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (Duration::ZERO, 1),
+                (Duration::ZERO, 2),
+                (Duration::from_millis(10), 3),
+                (Duration::from_millis(20), 0),
+            ]
+        );
+
+        timer.close();
+        assert!(timer.next().is_none());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use crate::{request::parse_decimal, response::Body};
+
+// a `Range` header asks for parts of a body by byte offset, so a download can be resumed:
+// ```
+// Range: bytes=0-499        the first 500 bytes
+// Range: bytes=500-         everything from byte 500 on
+// Range: bytes=-500         the last 500 bytes
+// Range: bytes=0-0,-1       the first and the last byte, sent as multipart/byteranges
+// ```
+
+// asking for more ranges than this is more likely an attack than a real download,
+// each range costs a part header and a seek, so such a header is ignored and the whole body sent
+const MAX_RANGES: usize = 32;
+
+// the byte ranges a `Range` header asks for out of a body of `len` bytes, in the order asked for
+// `None` if the header should be ignored because it's malformed, isn't in bytes, or asks for too much,
+// and an empty list if none of the ranges overlap the body, which is answered with 416
+pub fn parse(header: &str, len: u64) -> Option<Vec<Range<u64>>> {
+    let (unit, specs) = header.split_once('=')?;
+
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let specs: Vec<_> = specs.split(',').map(str::trim).collect();
+
+    if specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges = Vec::with_capacity(specs.len());
+
+    for spec in specs {
+        let (first, last) = spec.split_once('-')?;
+
+        let range = match (first, last) {
+            ("", suffix) => {
+                let suffix = parse_decimal(suffix)?;
+                // `-0` asks for nothing, so it can't be satisfied
+                (suffix > 0 && len > 0).then(|| len.saturating_sub(suffix)..len)
+            }
+            (first, "") => {
+                let first = parse_decimal(first)?;
+                (first < len).then_some(first..len)
+            }
+            (first, last) => {
+                let (first, last) = (parse_decimal(first)?, parse_decimal(last)?);
+                if last < first {
+                    return None;
+                }
+                (first < len).then(|| first..last.min(len - 1) + 1)
+            }
+        };
+
+        ranges.extend(range);
+    }
+
+    Some(ranges)
+}
+
+// the `Content-Range` of `range` out of `len` bytes, like `bytes 0-499/1234`
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+// a multipart/byteranges body with one part for each range of `source`:
+// ```
+// \r\n--<boundary>\r\n
+// Content-Type: text/html\r\n
+// Content-Range: bytes 0-0/1234\r\n
+// \r\n
+// <byte 0>
+// \r\n--<boundary>\r\n
+// ...
+// \r\n--<boundary>--\r\n
+// ```
+// the parts are read from `source` while the response is written, one after another
+pub fn multipart(
+    source: impl Read + Seek + Send + 'static,
+    ranges: &[Range<u64>],
+    len: u64,
+    content_type: &str,
+    boundary: &str,
+) -> Body {
+    let mut pieces = VecDeque::with_capacity(ranges.len() * 2 + 1);
+
+    for range in ranges {
+        let head = format!(
+            "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+            content_range(range, len)
+        );
+        pieces.push_back(Piece::Bytes(Cursor::new(head.into_bytes())));
+        pieces.push_back(Piece::Source(range.clone()));
+    }
+
+    let end = format!("\r\n--{boundary}--\r\n");
+    pieces.push_back(Piece::Bytes(Cursor::new(end.into_bytes())));
+
+    let total = pieces.iter().map(Piece::len).sum();
+
+    Body::from_reader(
+        Pieces {
+            source,
+            position: None,
+            pieces,
+        },
+        total,
+    )
+}
+
+// a part head, or a range of the source
+enum Piece {
+    Bytes(Cursor<Vec<u8>>),
+    Source(Range<u64>),
+}
+
+impl Piece {
+    fn len(&self) -> u64 {
+        match self {
+            Piece::Bytes(bytes) => bytes.get_ref().len() as u64,
+            Piece::Source(range) => range.end - range.start,
+        }
+    }
+}
+
+// reads the pieces of a multipart body in turn
+struct Pieces<R> {
+    source: R,
+    // where `source` is, so it's only sought when a range doesn't follow on from the last one
+    position: Option<u64>,
+    pieces: VecDeque<Piece>,
+}
+
+impl<R: Read + Seek> Read for Pieces<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(piece) = self.pieces.front_mut() {
+            let read = match piece {
+                Piece::Bytes(bytes) => bytes.read(buf)?,
+                Piece::Source(range) if range.is_empty() => 0,
+                Piece::Source(range) => {
+                    if self.position != Some(range.start) {
+                        self.source.seek(SeekFrom::Start(range.start))?;
+                    }
+
+                    let left = usize::try_from(range.end - range.start).unwrap_or(usize::MAX);
+                    let max = buf.len().min(left);
+                    let read = self.source.read(&mut buf[..max])?;
+
+                    if read == 0 {
+                        // the file got shorter since its length was sent
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "range ended early",
+                        ));
+                    }
+
+                    range.start += read as u64;
+                    self.position = Some(range.start);
+                    read
+                }
+            };
+
+            if read > 0 {
+                return Ok(read);
+            }
+
+            self.pieces.pop_front();
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the ranges as `(start, end)` pairs, which read better in asserts than a `Vec` of `Range`s
+    fn ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+        let ranges = parse(header, len)?;
+        Some(
+            ranges
+                .iter()
+                .map(|range| (range.start, range.end))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_every_kind_of_range() {
+        assert_eq!(ranges("bytes=0-499", 1000), Some(vec![(0, 500)]));
+        assert_eq!(ranges("bytes=500-", 1000), Some(vec![(500, 1000)]));
+        assert_eq!(ranges("bytes=-200", 1000), Some(vec![(800, 1000)]));
+        assert_eq!(
+            ranges("bytes=0-0, -1 ,10-19", 1000),
+            Some(vec![(0, 1), (999, 1000), (10, 20)])
+        );
+        assert_eq!(ranges("Bytes=0-0", 1000), Some(vec![(0, 1)]));
+    }
+
+    #[test]
+    fn ranges_are_cut_to_the_body() {
+        assert_eq!(ranges("bytes=900-2000", 1000), Some(vec![(900, 1000)]));
+        assert_eq!(ranges("bytes=-5000", 1000), Some(vec![(0, 1000)]));
+        // the ones that miss the body entirely are dropped
+        assert_eq!(ranges("bytes=1000-1100,0-1", 1000), Some(vec![(0, 2)]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(ranges("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(ranges("bytes=-0", 1000), Some(vec![]));
+        assert_eq!(ranges("bytes=0-0", 0), Some(vec![]));
+        assert_eq!(ranges("bytes=-10", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn ignores_what_it_cant_read() {
+        for header in [
+            "",
+            "bytes",
+            "bytes=",
+            "bytes=abc",
+            "bytes=5-1",
+            "bytes=+1-2",
+            "bytes=1-2-3",
+            "items=0-1",
+            "bytes=0-1,,2-3",
+        ] {
+            assert_eq!(ranges(header, 1000), None, "{header:?}");
+        }
+
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(ranges(&many, 1000), None);
+    }
+
+    #[test]
+    fn content_range_is_inclusive() {
+        assert_eq!(content_range(&(0..500), 1234), "bytes 0-499/1234");
+        assert_eq!(content_range(&(1233..1234), 1234), "bytes 1233-1233/1234");
+    }
+
+    #[test]
+    fn multipart_body() {
+        let source = Cursor::new(b"0123456789".to_vec());
+        let body = multipart(source, &[7..10, 0..2], 10, "text/plain", "XYZ");
+
+        let len = body.len();
+        let bytes = body.into_bytes().unwrap();
+
+        assert_eq!(
+            String::from_utf8(bytes.clone()).unwrap(),
+            "\r\n--XYZ\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Range: bytes 7-9/10\r\n\
+             \r\n\
+             789\
+             \r\n--XYZ\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Range: bytes 0-1/10\r\n\
+             \r\n\
+             01\
+             \r\n--XYZ--\r\n"
+        );
+        assert_eq!(len, Some(bytes.len() as u64));
+    }
+
+    #[test]
+    fn multipart_fails_if_the_source_is_short() {
+        let source = Cursor::new(b"01234".to_vec());
+        let body = multipart(source, &[3..8, 9..10], 10, "text/plain", "XYZ");
+
+        assert!(body.into_bytes().is_err());
+    }
+}
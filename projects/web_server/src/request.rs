@@ -0,0 +1,613 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read},
+    net::SocketAddr,
+    str::FromStr,
+};
+
+use crate::{
+    chunked::read_chunked_body, connection::is_timeout, headers::Headers, response::StatusCode,
+};
+
+// the request line and all headers together may not exceed this many bytes
+// otherwise a client could make us buffer an endless header section in memory
+pub const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+// the largest request body read into memory, for the same reason
+pub const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Trace,
+    Connect,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    // methods are case-sensitive, so `get` is not the same as `GET`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "PATCH" => Ok(Method::Patch),
+            "OPTIONS" => Ok(Method::Options),
+            "TRACE" => Ok(Method::Trace),
+            "CONNECT" => Ok(Method::Connect),
+            _ => Err(ParseError::UnsupportedMethod(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HTTP/1.0" => Ok(Version::Http10),
+            "HTTP/1.1" => Ok(Version::Http11),
+            _ => Err(ParseError::UnsupportedVersion(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    // the connection ended before a complete request arrived
+    UnexpectedEof,
+    // a line ended with a bare `\n` instead of `\r\n`
+    MissingCrlf,
+    MalformedRequestLine,
+    UnsupportedMethod(String),
+    UnsupportedVersion(String),
+    MalformedHeader,
+    HeadersTooLarge,
+    BodyTooLarge,
+    // the client didn't send the whole request in time
+    Timeout,
+    InvalidContentLength,
+    InvalidChunk,
+    UnsupportedTransferEncoding(String),
+    // both Content-Length and Transfer-Encoding were sent
+    // proxies may disagree on which one wins, which is how request smuggling works, so refuse
+    ConflictingLength,
+}
+
+impl ParseError {
+    // the status code to answer a request that failed to parse with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ParseError::UnsupportedMethod(_) => StatusCode::NotImplemented,
+            ParseError::UnsupportedVersion(_) => StatusCode::HttpVersionNotSupported,
+            ParseError::UnsupportedTransferEncoding(_) => StatusCode::NotImplemented,
+            ParseError::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::BodyTooLarge => StatusCode::PayloadTooLarge,
+            ParseError::Timeout => StatusCode::RequestTimeout,
+            _ => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(err) => write!(f, "i/o error while reading request: {err}"),
+            ParseError::UnexpectedEof => {
+                write!(f, "connection closed before the request was complete")
+            }
+            ParseError::MissingCrlf => write!(f, "request line or header not terminated by CRLF"),
+            ParseError::MalformedRequestLine => write!(f, "malformed request line"),
+            ParseError::UnsupportedMethod(method) => write!(f, "unsupported method {method:?}"),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported http version {version:?}")
+            }
+            ParseError::MalformedHeader => write!(f, "malformed header line"),
+            ParseError::HeadersTooLarge => write!(f, "request headers are too large"),
+            ParseError::BodyTooLarge => write!(f, "request body is too large"),
+            ParseError::Timeout => write!(f, "timed out waiting for the request"),
+            ParseError::InvalidContentLength => write!(f, "invalid Content-Length header"),
+            ParseError::InvalidChunk => write!(f, "malformed chunk in chunked body"),
+            ParseError::UnsupportedTransferEncoding(encoding) => {
+                write!(f, "unsupported transfer encoding {encoding:?}")
+            }
+            ParseError::ConflictingLength => {
+                write!(f, "both Content-Length and Transfer-Encoding were sent")
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            ParseError::UnexpectedEof
+        } else if is_timeout(&err) {
+            ParseError::Timeout
+        } else {
+            ParseError::Io(err)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    // everything after the `?` in the request target, without the `?`
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    // the address of the client, filled in by the connection that read the request
+    pub peer_addr: Option<SocketAddr>,
+}
+
+impl Request {
+    pub fn new(method: Method, target: &str) -> Self {
+        let (path, query) = split_target(target);
+
+        Self {
+            method,
+            path,
+            query,
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            peer_addr: None,
+        }
+    }
+
+    // reads exactly one request off `reader`
+    // a request looks like:
+    // ```
+    // GET /path?query HTTP/1.1\r\n
+    // Header-Name: value\r\n
+    // \r\n
+    // <Content-Length bytes of body, or a chunked body>
+    // ```
+    pub fn parse(reader: &mut impl BufRead) -> Result<Self, ParseError> {
+        Self::parse_limited(reader, MAX_HEAD_SIZE, MAX_BODY_SIZE)
+    }
+
+    // like `parse`, but with the given limits instead of `MAX_HEAD_SIZE` and `MAX_BODY_SIZE`
+    pub fn parse_limited(
+        reader: &mut impl BufRead,
+        max_head_size: usize,
+        max_body_size: u64,
+    ) -> Result<Self, ParseError> {
+        let mut budget = max_head_size;
+
+        let request_line = read_line(reader, &mut budget)?;
+        let request_line =
+            String::from_utf8(request_line).map_err(|_| ParseError::MalformedRequestLine)?;
+
+        let parts: Vec<_> = request_line.split(' ').collect();
+        let [method, target, version] = parts[..] else {
+            return Err(ParseError::MalformedRequestLine);
+        };
+
+        let method: Method = method.parse()?;
+        let version: Version = version.parse()?;
+
+        // only origin-form targets (`/path`) are supported, plus `*` for `OPTIONS * HTTP/1.1`
+        if !(target.starts_with('/') || (target == "*" && method == Method::Options)) {
+            return Err(ParseError::MalformedRequestLine);
+        }
+
+        let (path, query) = split_target(target);
+
+        let mut headers = Headers::new();
+
+        loop {
+            let line = read_line(reader, &mut budget)?;
+
+            if line.is_empty() {
+                // an empty line marks the end of the header section
+                break;
+            }
+
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+
+        let body = read_body(reader, &headers, max_head_size, max_body_size)?;
+
+        Ok(Self {
+            method,
+            path,
+            query,
+            version,
+            headers,
+            body,
+            peer_addr: None,
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+}
+
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+// reads one CRLF terminated line, without the CRLF
+// every byte read is charged against `budget`, so the whole head of the request stays bounded
+pub(crate) fn read_line(
+    reader: &mut impl BufRead,
+    budget: &mut usize,
+) -> Result<Vec<u8>, ParseError> {
+    if *budget == 0 {
+        return Err(ParseError::HeadersTooLarge);
+    }
+
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(*budget as u64)
+        .read_until(b'\n', &mut line)?;
+    // `take` stops `read_until` from reading past the budget
+    // even if the client never sends a newline
+
+    *budget -= read;
+
+    if !line.ends_with(b"\n") {
+        return Err(if *budget == 0 {
+            ParseError::HeadersTooLarge
+        } else {
+            ParseError::UnexpectedEof
+        });
+    }
+
+    if !line.ends_with(b"\r\n") {
+        return Err(ParseError::MissingCrlf);
+    }
+
+    line.truncate(line.len() - 2);
+
+    Ok(line)
+}
+
+fn parse_header(line: &[u8]) -> Result<(String, String), ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::MalformedHeader)?;
+
+    let (name, value) = line.split_once(':').ok_or(ParseError::MalformedHeader)?;
+
+    // no whitespace is allowed in or around the name
+    // this also rejects obsolete line folding, where a header continues on a line starting with a space
+    if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace() || c.is_control()) {
+        return Err(ParseError::MalformedHeader);
+    }
+
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+fn read_body(
+    reader: &mut impl BufRead,
+    headers: &Headers,
+    max_head_size: usize,
+    max_size: u64,
+) -> Result<Vec<u8>, ParseError> {
+    if headers.contains("Transfer-Encoding") {
+        if headers.contains("Content-Length") {
+            return Err(ParseError::ConflictingLength);
+        }
+
+        // only a body that's just chunked is supported, not one that's also compressed like `gzip, chunked`
+        let encodings: Vec<_> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|encoding| !encoding.is_empty())
+            .collect();
+
+        return match encodings[..] {
+            [encoding] if encoding.eq_ignore_ascii_case("chunked") => {
+                read_chunked_body(reader, max_head_size, max_size)
+            }
+            _ => Err(ParseError::UnsupportedTransferEncoding(
+                encodings.join(", "),
+            )),
+        };
+    }
+
+    let mut lengths = headers.get_all("Content-Length");
+
+    let length = match lengths.next() {
+        Some(value) => parse_decimal(value).ok_or(ParseError::InvalidContentLength)?,
+        None => return Ok(Vec::new()),
+    };
+
+    // repeated Content-Length headers are only allowed if they all agree
+    if lengths.any(|other| parse_decimal(other) != Some(length)) {
+        return Err(ParseError::InvalidContentLength);
+    }
+
+    // refuse before reading anything, there's no point receiving a body we won't accept
+    if length > max_size {
+        return Err(ParseError::BodyTooLarge);
+    }
+
+    let mut body = Vec::new();
+    reader.take(length).read_to_end(&mut body)?;
+    // reading through `take` instead of allocating `length` bytes up front
+    // means a huge Content-Length doesn't allocate memory the client never sends
+
+    if (body.len() as u64) < length {
+        return Err(ParseError::UnexpectedEof);
+    }
+
+    Ok(body)
+}
+
+// only plain decimal, for numbers like `Content-Length` or a byte range's offsets
+// `+5` would parse as a u64 but a proxy in front of us may read it differently,
+// and two parties disagreeing on where a body ends is how requests get smuggled
+pub(crate) fn parse_decimal(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::parse(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn simple_get() {
+        let request =
+            parse("GET /hello?name=rust&x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/hello");
+        assert_eq!(request.query.as_deref(), Some("name=rust&x=1"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn body_is_read_by_content_length() {
+        let request =
+            parse("POST /submit HTTP/1.0\r\ncontent-LENGTH: 5\r\n\r\nhello, extra bytes").unwrap();
+
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.version, Version::Http10);
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn leaves_next_request_in_reader() {
+        let raw = "GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        let mut reader = raw.as_bytes();
+
+        assert_eq!(Request::parse(&mut reader).unwrap().path, "/a");
+        assert_eq!(Request::parse(&mut reader).unwrap().path, "/b");
+    }
+
+    #[test]
+    fn malformed_request_lines() {
+        for raw in [
+            "GET\r\n\r\n",
+            "GET /\r\n\r\n",
+            "GET  / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "GET hello HTTP/1.1\r\n\r\n",
+            "\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(ParseError::MalformedRequestLine)),
+                "{raw:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_method_and_version() {
+        assert!(matches!(
+            parse("get / HTTP/1.1\r\n\r\n"),
+            Err(ParseError::UnsupportedMethod(method)) if method == "get"
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/2.0\r\n\r\n"),
+            Err(ParseError::UnsupportedVersion(version)) if version == "HTTP/2.0"
+        ));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        for raw in [
+            "GET / HTTP/1.1\r\nno colon here\r\n\r\n",
+            "GET / HTTP/1.1\r\n: empty name\r\n\r\n",
+            "GET / HTTP/1.1\r\nBad Name: value\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(ParseError::MalformedHeader)),
+                "{raw:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_crlf() {
+        assert!(matches!(
+            parse("GET / HTTP/1.1\n\n"),
+            Err(ParseError::MissingCrlf)
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: localhost\n\r\n"),
+            Err(ParseError::MissingCrlf)
+        ));
+    }
+
+    #[test]
+    fn incomplete_requests() {
+        // a client that sends headers but never the blank line
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: localhost\r\n"),
+            Err(ParseError::UnexpectedEof)
+        ));
+        assert!(matches!(parse(""), Err(ParseError::UnexpectedEof)));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn oversized_headers() {
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD_SIZE)
+        );
+        assert!(matches!(parse(&raw), Err(ParseError::HeadersTooLarge)));
+
+        // lots of small headers add up too
+        let raw = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Small: a\r\n".repeat(MAX_HEAD_SIZE / 10)
+        );
+        assert!(matches!(parse(&raw), Err(ParseError::HeadersTooLarge)));
+    }
+
+    #[test]
+    fn configured_limits() {
+        let raw = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+
+        assert!(Request::parse_limited(&mut raw.as_bytes(), 64, 5).is_ok());
+        assert!(matches!(
+            Request::parse_limited(&mut raw.as_bytes(), 32, 5),
+            Err(ParseError::HeadersTooLarge)
+        ));
+        assert!(matches!(
+            Request::parse_limited(&mut raw.as_bytes(), 64, 4),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn body_too_large_is_refused_before_reading_it() {
+        // the body never arrives, but the Content-Length alone is enough to refuse
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        let error = parse(&raw).unwrap_err();
+
+        assert!(matches!(error, ParseError::BodyTooLarge));
+        assert_eq!(error.status(), StatusCode::PayloadTooLarge);
+    }
+
+    #[test]
+    fn chunked_body() {
+        let request = parse(
+            "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.body, b"Wikipedia");
+    }
+
+    #[test]
+    fn unsupported_and_conflicting_transfer_encodings() {
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+            Err(ParseError::UnsupportedTransferEncoding(encoding)) if encoding == "gzip, chunked"
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n"),
+            Err(ParseError::ConflictingLength)
+        ));
+    }
+
+    #[test]
+    fn invalid_content_length() {
+        for raw in [
+            "POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: +5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(ParseError::InvalidContentLength)),
+                "{raw:?} should be rejected"
+            );
+        }
+    }
+}
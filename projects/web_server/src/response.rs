@@ -0,0 +1,616 @@
+use std::{
+    fmt,
+    io::{self, BufWriter, Read, Write},
+    mem,
+    net::TcpStream,
+};
+
+use crate::{chunked::ChunkedWriter, headers::Headers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    SwitchingProtocols,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    UpgradeRequired,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+}
+
+impl StatusCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            StatusCode::SwitchingProtocols => 101,
+            StatusCode::Ok => 200,
+            StatusCode::Created => 201,
+            StatusCode::Accepted => 202,
+            StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::SeeOther => 303,
+            StatusCode::NotModified => 304,
+            StatusCode::TemporaryRedirect => 307,
+            StatusCode::PermanentRedirect => 308,
+            StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
+            StatusCode::Forbidden => 403,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::Conflict => 409,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::UpgradeRequired => 426,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
+            StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
+            StatusCode::ServiceUnavailable => 503,
+            StatusCode::HttpVersionNotSupported => 505,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::Accepted => "Accepted",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    // 1xx, 204 and 304 responses never carry a body
+    pub fn allows_body(&self) -> bool {
+        let code = self.code();
+        !(code < 200 || code == 204 || code == 304)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+// produces a body of unknown length by writing into the given writer
+// flushing the writer pushes everything written so far out to the client
+pub type StreamFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    // copied from `reader` while the response is being written
+    // so a large file never has to be loaded into memory all at once
+    Reader {
+        reader: Box<dyn Read + Send>,
+        len: u64,
+    },
+    // generated while the response is being written, and sent with chunked transfer encoding
+    Chunked(StreamFn),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Bytes(Vec::new())
+    }
+
+    // `reader` must produce exactly `len` bytes, since `len` is sent as the Content-Length
+    pub fn from_reader(reader: impl Read + Send + 'static, len: u64) -> Self {
+        Body::Reader {
+            reader: Box::new(reader),
+            len,
+        }
+    }
+
+    // for output that's produced bit by bit, like a long report:
+    // ```
+    // Body::stream(|writer| {
+    //     for row in rows {
+    //         writeln!(writer, "{row}")?;
+    //     }
+    //     Ok(())
+    // })
+    // ```
+    pub fn stream<F>(produce: F) -> Self
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        Body::Chunked(Box::new(produce))
+    }
+
+    // `None` for a streamed body, whose length isn't known until it has been sent
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader { len, .. } => Some(*len),
+            Body::Chunked(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    // only in-memory bodies can be borrowed, the others have to be consumed with `into_bytes`
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Reader { .. } | Body::Chunked(_) => None,
+        }
+    }
+
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Reader { reader, len } => {
+                let mut bytes = Vec::new();
+                reader.take(len).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Body::Chunked(produce) => {
+                let mut bytes = Vec::new();
+                produce(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    // returns the number of body bytes written, not counting any chunk framing
+    // a streamed body is written without chunk framing unless `chunked` is set
+    fn write_to(self, writer: &mut impl Write, chunked: bool) -> io::Result<u64> {
+        match self {
+            Body::Bytes(bytes) => {
+                writer.write_all(&bytes)?;
+                Ok(bytes.len() as u64)
+            }
+            Body::Reader { reader, len } => {
+                let copied = io::copy(&mut reader.take(len), writer)?;
+
+                // the Content-Length has already been sent, so a short read leaves the response broken
+                if copied < len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("body ended after {copied} of {len} bytes"),
+                    ));
+                }
+
+                Ok(copied)
+            }
+            Body::Chunked(produce) if !chunked => {
+                let mut counting = CountingWriter {
+                    inner: writer,
+                    written: 0,
+                };
+
+                {
+                    let mut buffered = BufWriter::new(&mut counting);
+                    produce(&mut buffered)?;
+                    buffered.flush()?;
+                }
+
+                Ok(counting.written)
+            }
+            Body::Chunked(produce) => {
+                let mut chunked = ChunkedWriter::new(writer);
+
+                {
+                    let mut buffered = BufWriter::new(&mut chunked);
+                    // without the buffer every small `write!` would become its own chunk
+                    produce(&mut buffered)?;
+                    buffered.flush()?;
+                }
+
+                chunked.finish()
+            }
+        }
+    }
+}
+
+// passes everything through, counting the bytes for the access log
+struct CountingWriter<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Reader { len, .. } => f.debug_struct("Reader").field("len", len).finish(),
+            Body::Chunked(_) => f.write_str("Chunked"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+// takes the connection over from HTTP once the head of the response has been sent
+// after a 101 Switching Protocols it speaks the other protocol, after any other status it writes
+// the rest of the body, which ends where the connection does, since the head has no length for it
+// it's handed the socket and whatever the client sent after the request that was already read,
+// and runs on the connection's worker, so anything long-lived belongs on a thread of its own
+pub struct Upgrade {
+    run: Box<dyn FnOnce(TcpStream, Vec<u8>) + Send>,
+}
+
+impl Upgrade {
+    pub fn new<F>(run: F) -> Self
+    where
+        F: FnOnce(TcpStream, Vec<u8>) + Send + 'static,
+    {
+        Self { run: Box::new(run) }
+    }
+
+    pub(crate) fn run(self, stream: TcpStream, buffered: Vec<u8>) {
+        (self.run)(stream, buffered)
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Body,
+    // gets the connection once the head has been sent, see `Upgrade`
+    pub upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Body::empty(),
+            upgrade: None,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(StatusCode::Ok)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(StatusCode::NotFound)
+    }
+
+    pub fn html(body: impl Into<Body>) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn text(body: impl Into<Body>) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    // the body is expected to already be serialized json
+    pub fn json(body: impl Into<Body>) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "application/json")
+            .with_body(body)
+    }
+
+    pub fn redirect(status: StatusCode, location: &str) -> Self {
+        Self::new(status).with_header("Location", location)
+    }
+
+    // builder style methods take `self` by value, so they can be chained
+    // `Response::ok().with_header(...).with_body(...)`
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn with_upgrade(mut self, upgrade: Upgrade) -> Self {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    // serializes the response onto `writer`:
+    // ```
+    // HTTP/1.1 200 OK\r\n
+    // Header-Name: value\r\n
+    // Content-Length: <length of body>\r\n
+    // \r\n
+    // <body>
+    // ```
+    // a streamed body is sent with `Transfer-Encoding: chunked` instead of a Content-Length
+    // returns the number of body bytes written, which is what the access log records
+    pub fn write_to(&mut self, writer: &mut impl Write) -> io::Result<u64> {
+        self.write_framed(writer, true)
+    }
+
+    // the same for HTTP/1.0 clients, which don't understand chunked encoding
+    // a streamed body is sent as it is and ends where the connection does,
+    // so the caller has to close the connection afterwards
+    pub fn write_close_delimited(&mut self, writer: &mut impl Write) -> io::Result<u64> {
+        self.write_framed(writer, false)
+    }
+
+    fn write_framed(&mut self, writer: &mut impl Write, chunked: bool) -> io::Result<u64> {
+        // the upgrade writes the rest of the body, so it's neither chunked nor of a known length
+        let chunked = chunked && self.upgrade.is_none();
+
+        if self.upgrade.is_some() {
+            self.headers.remove("Content-Length");
+            self.headers.remove("Transfer-Encoding");
+        } else if self.status.allows_body() {
+            match self.body.len() {
+                None => {
+                    self.headers.remove("Content-Length");
+                    if chunked {
+                        self.headers.set("Transfer-Encoding", "chunked");
+                    }
+                }
+                Some(len) => {
+                    if !self.headers.contains("Content-Length")
+                        && !self.headers.contains("Transfer-Encoding")
+                    {
+                        self.headers.set("Content-Length", len.to_string());
+                    }
+                }
+            }
+        }
+
+        let head = format!("HTTP/1.1 {}\r\n{}\r\n", self.status, self.headers);
+        writer.write_all(head.as_bytes())?;
+
+        let bytes = if self.status.allows_body() {
+            // the body is consumed by writing it, so leave an empty one behind
+            mem::take(&mut self.body).write_to(writer, chunked)?
+        } else {
+            0
+        };
+
+        writer.flush()?;
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_bytes(mut response: Response) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        response.write_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn writes_status_headers_and_body() {
+        let response = Response::html("<h1>hi</h1>").with_header("X-Custom", "yes");
+
+        assert_eq!(
+            String::from_utf8(to_bytes(response)).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             X-Custom: yes\r\n\
+             Content-Length: 11\r\n\
+             \r\n\
+             <h1>hi</h1>"
+        );
+    }
+
+    #[test]
+    fn binary_body() {
+        let png_magic = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        let response = Response::ok()
+            .with_header("Content-Type", "image/png")
+            .with_body(png_magic.clone());
+
+        let bytes = to_bytes(response);
+        assert!(bytes.ends_with(&png_magic));
+        assert!(bytes.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn streamed_body() {
+        let contents = "streamed from a reader".repeat(1000);
+        let response = Response::ok().with_body(Body::from_reader(
+            io::Cursor::new(contents.clone()),
+            contents.len() as u64,
+        ));
+
+        let bytes = to_bytes(response);
+        let expected_head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            contents.len()
+        );
+        assert_eq!(&bytes[..expected_head.len()], expected_head.as_bytes());
+        assert_eq!(&bytes[expected_head.len()..], contents.as_bytes());
+    }
+
+    #[test]
+    fn streamed_body_is_chunked() {
+        let response = Response::text("").with_body(Body::stream(|writer| {
+            write!(writer, "first,")?;
+            writer.flush()?;
+            write!(writer, "second")
+        }));
+
+        assert_eq!(
+            String::from_utf8(to_bytes(response)).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Transfer-Encoding: chunked\r\n\
+             \r\n\
+             6\r\nfirst,\r\n6\r\nsecond\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn streamed_body_close_delimited() {
+        let mut response = Response::text("").with_body(Body::stream(|writer| {
+            write!(writer, "first,")?;
+            writer.flush()?;
+            write!(writer, "second")
+        }));
+
+        let mut bytes = Vec::new();
+        assert_eq!(response.write_close_delimited(&mut bytes).unwrap(), 12);
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             \r\n\
+             first,second"
+        );
+    }
+
+    #[test]
+    fn upgrade_writes_the_rest_of_the_body() {
+        let response = Response::text("first,")
+            .with_header("Content-Length", "6")
+            .with_upgrade(Upgrade::new(|_, _| {}));
+
+        assert_eq!(
+            String::from_utf8(to_bytes(response)).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             \r\n\
+             first,"
+        );
+    }
+
+    #[test]
+    fn streamed_body_into_bytes() {
+        let body = Body::stream(|writer| writer.write_all(b"collected"));
+
+        assert_eq!(body.len(), None);
+        assert_eq!(body.into_bytes().unwrap(), b"collected");
+    }
+
+    #[test]
+    fn short_reader_is_an_error() {
+        let mut response = Response::ok().with_body(Body::from_reader(&b"short"[..], 10));
+
+        let err = response.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn redirect_has_location_and_no_body() {
+        let response = Response::redirect(StatusCode::SeeOther, "/login");
+
+        assert_eq!(
+            String::from_utf8(to_bytes(response)).unwrap(),
+            "HTTP/1.1 303 See Other\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn not_modified_never_has_body() {
+        let response = Response::new(StatusCode::NotModified).with_body("ignored");
+
+        assert_eq!(
+            String::from_utf8(to_bytes(response)).unwrap(),
+            "HTTP/1.1 304 Not Modified\r\n\r\n"
+        );
+    }
+}
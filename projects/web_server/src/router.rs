@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use crate::{
+    request::{Method, Request},
+    response::{Body, Response, StatusCode},
+    websocket::{self, WebSocketHandler},
+};
+
+// a Handler is shared between every worker thread, so it must be `Send + Sync`
+// and it's called once per request, so it's a `Fn` rather than a `FnOnce` like a `Job`
+pub type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync + 'static>;
+
+// the values captured from the path by `:name` and `*name` segments of a route pattern
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    entries: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    // must match the path segment exactly
+    Literal(String),
+    // `:name` matches any single non-empty segment
+    Param(String),
+    // `*name` matches the rest of the path, and can only be the last segment
+    Wildcard(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        assert!(
+            pattern.starts_with('/'),
+            "route pattern {pattern:?} must start with '/'"
+        );
+
+        let parts: Vec<_> = pattern.split('/').skip(1).collect();
+        let mut segments = Vec::with_capacity(parts.len());
+
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert!(
+                    i == parts.len() - 1,
+                    "wildcard must be the last segment of {pattern:?}"
+                );
+                // a bare `*` is still captured, under the name "*"
+                let name = if name.is_empty() { "*" } else { name };
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+
+            segments.push(segment);
+        }
+
+        Self { segments }
+    }
+
+    // returns the captured params if `path` matches this pattern
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path.split('/').skip(1);
+        // "/users/42" splits into ["", "users", "42"], so skip the leading empty string
+
+        let mut params = Params::default();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = parts.next().filter(|part| !part.is_empty())?;
+                    params.entries.push((name.clone(), value.to_string()));
+                }
+                Segment::Wildcard(name) => {
+                    let rest: Vec<_> = parts.by_ref().collect();
+                    params.entries.push((name.clone(), rest.join("/")));
+                }
+            }
+        }
+
+        // every segment of the path has to be consumed by the pattern
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(params)
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Handler,
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    // registers `handler` for requests with `method` whose path matches `pattern`
+    // patterns look like `/users/:id` or `/static/*path`
+    // routes are tried in the order they were added, the first match wins
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    // answers WebSocket handshakes on `pattern`, and runs `handler` on each socket
+    // a handler that needs the request or the params can call `websocket::upgrade` from a `get` route
+    pub fn websocket(self, pattern: &str, handler: impl WebSocketHandler) -> Self {
+        let handler = Arc::new(handler);
+
+        self.get(pattern, move |request, _| {
+            let handler = Arc::clone(&handler);
+            websocket::upgrade(request, move |socket| handler.handle(socket))
+        })
+    }
+
+    // called when no route matches the path, instead of the default empty 404
+    pub fn fallback<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        // a HEAD request is answered by the GET handler, minus the body
+        let is_head = request.method == Method::Head;
+
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = route.pattern.matches(&request.path) else {
+                continue;
+            };
+
+            if route.method == request.method || (is_head && route.method == Method::Get) {
+                let response = (route.handler)(request, &params);
+
+                return if is_head {
+                    strip_body(response)
+                } else {
+                    response
+                };
+            }
+
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if !allowed.is_empty() {
+            // the path exists, just not for this method
+            if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+                allowed.push(Method::Head);
+            }
+
+            let allow: Vec<_> = allowed.iter().map(Method::as_str).collect();
+
+            return Response::new(StatusCode::MethodNotAllowed)
+                .with_header("Allow", allow.join(", "));
+        }
+
+        let response = match &self.fallback {
+            Some(fallback) => fallback(request, &Params::default()),
+            None => Response::not_found(),
+        };
+
+        // a fallback sees HEAD requests too, and a body it leaves would be taken for the next response
+        if is_head {
+            strip_body(response)
+        } else {
+            response
+        }
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn strip_body(mut response: Response) -> Response {
+    // a 304 or 204 wouldn't have had a body for GET either, so there's no length to report
+    if !response.status.allows_body() {
+        return response;
+    }
+
+    // keep the framing headers the GET response would have had
+    match response.body.len() {
+        Some(len) if !response.headers.contains("Content-Length") => {
+            response.headers.set("Content-Length", len.to_string());
+        }
+        None => response.headers.set("Transfer-Encoding", "chunked"),
+        _ => {}
+    }
+
+    response.body = Body::empty();
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_params(_: &Request, params: &Params) -> Response {
+        let pairs: Vec<_> = params.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        Response::text(pairs.join("&"))
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body.as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn literal_routes() {
+        let router = Router::new()
+            .get("/", |_, _| Response::text("index"))
+            .get("/about", |_, _| Response::text("about"));
+
+        assert_eq!(
+            body(&router.handle(&Request::new(Method::Get, "/"))),
+            "index"
+        );
+        assert_eq!(
+            body(&router.handle(&Request::new(Method::Get, "/about?x=1"))),
+            "about"
+        );
+        assert_eq!(
+            router
+                .handle(&Request::new(Method::Get, "/about/more"))
+                .status,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn path_params() {
+        let router = Router::new().get("/users/:id/posts/:post", echo_params);
+
+        let response = router.handle(&Request::new(Method::Get, "/users/42/posts/7"));
+        assert_eq!(body(&response), "id=42&post=7");
+
+        // params never match an empty segment
+        let response = router.handle(&Request::new(Method::Get, "/users//posts/7"));
+        assert_eq!(response.status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn wildcard_tail() {
+        let router = Router::new()
+            .get("/static/*path", echo_params)
+            .get("/any/*", echo_params);
+
+        let response = router.handle(&Request::new(Method::Get, "/static/css/site.css"));
+        assert_eq!(body(&response), "path=css/site.css");
+
+        let response = router.handle(&Request::new(Method::Get, "/static"));
+        assert_eq!(body(&response), "path=");
+
+        let response = router.handle(&Request::new(Method::Get, "/any/thing"));
+        assert_eq!(body(&response), "*=thing");
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let router = Router::new()
+            .get("/users/me", |_, _| Response::text("me"))
+            .get("/users/:id", echo_params);
+
+        assert_eq!(
+            body(&router.handle(&Request::new(Method::Get, "/users/me"))),
+            "me"
+        );
+        assert_eq!(
+            body(&router.handle(&Request::new(Method::Get, "/users/3"))),
+            "id=3"
+        );
+    }
+
+    #[test]
+    fn method_not_allowed_lists_allowed_methods() {
+        let router = Router::new()
+            .get("/items", |_, _| Response::text("list"))
+            .post("/items", |_, _| Response::new(StatusCode::Created));
+
+        let response = router.handle(&Request::new(Method::Delete, "/items"));
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(response.headers.get("Allow"), Some("GET, POST, HEAD"));
+
+        let response = router.handle(&Request::new(Method::Post, "/items"));
+        assert_eq!(response.status, StatusCode::Created);
+    }
+
+    #[test]
+    fn head_uses_get_handler_without_body() {
+        let router = Router::new().get("/", |_, _| Response::text("hello"));
+
+        let response = router.handle(&Request::new(Method::Head, "/"));
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn head_of_a_bodiless_status_has_no_length() {
+        let router = Router::new().get("/", |_, _| Response::new(StatusCode::NotModified));
+
+        let response = router.handle(&Request::new(Method::Head, "/"));
+        assert_eq!(response.status, StatusCode::NotModified);
+        assert!(!response.headers.contains("Content-Length"));
+    }
+
+    #[test]
+    fn fallback_handles_unknown_paths() {
+        let router = Router::new()
+            .get("/", |_, _| Response::text("index"))
+            .fallback(|request, _| {
+                Response::text(format!("no {}", request.path)).with_status(StatusCode::NotFound)
+            });
+
+        let response = router.handle(&Request::new(Method::Get, "/missing"));
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert_eq!(body(&response), "no /missing");
+    }
+
+    #[test]
+    fn head_on_the_fallback_has_no_body() {
+        let router = Router::new()
+            .fallback(|_, _| Response::html("<h1>not here</h1>").with_status(StatusCode::NotFound));
+
+        let response = router.handle(&Request::new(Method::Head, "/missing"));
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert_eq!(response.headers.get("Content-Length"), Some("17"));
+        assert!(response.body.is_empty());
+    }
+}
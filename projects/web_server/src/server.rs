@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{
+        IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    access_log::AccessLog,
+    connection::{handle_connection, reject_connection, Closer, KeepAlive, Limits, Service},
+    log,
+    metrics::Metrics,
+    middleware::Middleware,
+    pool::{self, lock, Overflow, Priority},
+    router::Router,
+};
+
+// ties the listener, the thread pool and the router together
+// ```
+// let server = Server::bind("127.0.0.1:7878", 4)?;
+// let shutdown = server.shutdown_handle();
+// server.run(router)?; // returns once `shutdown.shutdown()` has been called
+// ```
+pub struct Server {
+    listener: TcpListener,
+    workers: usize,
+    max_workers: usize,
+    worker_idle_timeout: Duration,
+    keep_alive: KeepAlive,
+    limits: Limits,
+    queue_capacity: usize,
+    access_log: Option<AccessLog>,
+    shutdown_timeout: Duration,
+    shutdown: ShutdownHandle,
+    metrics: Metrics,
+    periodic_tasks: Vec<(Duration, PeriodicTask)>,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+type PeriodicTask = Box<dyn Fn() + Send + Sync>;
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, workers: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle::new(listener.local_addr()?);
+
+        Ok(Self {
+            listener,
+            workers,
+            max_workers: workers,
+            worker_idle_timeout: Duration::from_secs(60),
+            keep_alive: KeepAlive::default(),
+            limits: Limits::default(),
+            queue_capacity: 64,
+            access_log: None,
+            shutdown_timeout: Duration::from_secs(30),
+            shutdown,
+            metrics: Metrics::new(),
+            periodic_tasks: Vec::new(),
+            middleware: Vec::new(),
+        })
+    }
+
+    // lets the pool grow to `max` workers while connections are waiting for one
+    // the extra workers stop again once they've had nothing to do for the worker idle timeout
+    pub fn with_max_workers(mut self, max: usize) -> Self {
+        self.max_workers = max;
+        self
+    }
+
+    pub fn with_worker_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.worker_idle_timeout = timeout;
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    // timeouts and size limits for each request
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // how many accepted connections may wait for a free worker
+    // beyond that, new connections are answered with 503 Service Unavailable right away
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    // every request is recorded here once its response has been sent
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    // how long in-flight requests get to finish once shutdown starts
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    // wraps `middleware` around every request, inside any added before it
+    pub fn with_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    // runs `task` every `interval` on the worker pool while the server runs, like expiring a cache
+    // it runs at low priority, so connections waiting for a worker are served first
+    // panics if `interval` is zero
+    pub fn with_periodic_task(
+        mut self,
+        interval: Duration,
+        task: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "periodic tasks need an interval above zero"
+        );
+        self.periodic_tasks.push((interval, Box::new(task)));
+        self
+    }
+
+    // the address actually bound, useful after binding to port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // the stats of the thread pool once `run` has started it, for a `/metrics` route
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    // accepts connections until shutdown is requested, then waits for in-flight requests
+    // returns `Ok(true)` if everything finished before the shutdown timeout
+    pub fn run(self, router: Router) -> io::Result<bool> {
+        let pool = pool::Builder::new(self.workers)
+            .with_max_workers(self.max_workers.max(self.workers))
+            .with_idle_timeout(self.worker_idle_timeout)
+            .with_queue_capacity(self.queue_capacity)
+            .with_overflow(Overflow::Reject)
+            .build()
+            .map_err(io::Error::other)?;
+        // running out of threads is reported like any other startup error instead of panicking
+        self.metrics.attach(pool.stats_handle());
+        for (interval, task) in self.periodic_tasks {
+            // stopped by `join_timeout` at shutdown, so the token isn't needed
+            pool.priority(Priority::Low).execute_every(interval, task);
+        }
+        let service = Arc::new(Service {
+            router,
+            keep_alive: self.keep_alive,
+            limits: self.limits,
+            access_log: self.access_log,
+            middleware: self.middleware,
+        });
+        // every worker needs to read the same route table and log, so share them with an Arc
+
+        let closer = Closer::spawn();
+        // turned away connections are handed to it to finish closing
+
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        // a clone of every open connection, so shutdown can interrupt the ones waiting for a request
+
+        for (id, stream) in self.listener.incoming().enumerate() {
+            // iterating through connection attempts
+
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn(format_args!("Failed to accept connection: {err}"));
+                    continue;
+                }
+            };
+
+            if let Ok(clone) = stream.try_clone() {
+                lock(&connections).insert(id, clone);
+            }
+
+            let job = {
+                let service = Arc::clone(&service);
+                let connections = Arc::clone(&connections);
+
+                move || {
+                    let _untrack = Untrack { connections, id };
+                    // dropped even if the handler panics, so the clone doesn't keep the socket open
+
+                    if let Err(err) = handle_connection(stream, &service) {
+                        // usually just a client that went away mid-response
+                        log::debug(format_args!("Connection error: {err}"));
+                    }
+                }
+            };
+
+            if pool.try_execute(job).is_err() {
+                // every worker is busy and enough connections are already waiting,
+                // queueing more would only use up memory, so turn this one away
+                // the refused job took the stream with it, but the clone is still open
+                log::warn("All workers busy, refusing connection");
+
+                let clone = lock(&connections).remove(&id);
+                if let Some(stream) = clone {
+                    if let Err(err) = reject_connection(stream, &service, &closer) {
+                        log::debug(format_args!("Connection error: {err}"));
+                    }
+                }
+            }
+        }
+
+        drop(self.listener);
+        // stop accepting, new clients now get "connection refused"
+
+        for stream in lock(&connections).values() {
+            let _ = stream.shutdown(Shutdown::Read);
+            // a keep-alive connection idling in `read` now sees end of file and closes
+            // while a request that's already being handled can still write its response
+        }
+
+        Ok(pool.join_timeout(self.shutdown_timeout))
+    }
+}
+
+// removes a connection's clone from the shutdown list once its job is over, however it ends
+struct Untrack {
+    connections: Arc<Mutex<HashMap<usize, TcpStream>>>,
+    id: usize,
+}
+
+impl Drop for Untrack {
+    fn drop(&mut self) {
+        lock(&self.connections).remove(&self.id);
+    }
+}
+
+// cheap to clone, so it can be moved into signal watchers and route handlers
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Debug)]
+struct ShutdownState {
+    requested: AtomicBool,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            state: Arc::new(ShutdownState {
+                requested: AtomicBool::new(false),
+                addr,
+            }),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            // somebody already asked
+            return;
+        }
+
+        // the accept loop is blocked in `accept`, so connect to it once to wake it up
+        let mut addr = self.state.addr;
+        if addr.ip().is_unspecified() {
+            // a listener on 0.0.0.0 or [::] can still be reached through loopback
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+
+        let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+}
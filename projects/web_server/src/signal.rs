@@ -0,0 +1,68 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::{log, server::ShutdownHandle};
+
+// set from the signal handler, which may only do async-signal-safe things like storing an atomic
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+// triggers `handle.shutdown()` when the process receives SIGINT (ctrl-c) or SIGTERM
+// a signal handler can't safely take locks or open sockets,
+// so it only sets a flag, and a watcher thread does the actual shutdown
+pub fn shutdown_on_signal(handle: ShutdownHandle) -> io::Result<()> {
+    install_handlers()?;
+
+    thread::Builder::new()
+        .name(String::from("signal-watcher"))
+        .spawn(move || {
+            while !RECEIVED.load(Ordering::SeqCst) && !handle.is_shutdown() {
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            if !handle.is_shutdown() {
+                log::info("Received shutdown signal, stopping");
+                handle.shutdown();
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn install_handlers() -> io::Result<()> {
+    use std::os::raw::c_int;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_ERR: usize = usize::MAX;
+
+    // `signal` comes from the C library that std already links against
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_signal(_signum: c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: `on_signal` only stores to an atomic, which is async-signal-safe
+        let previous = unsafe { signal(signum, on_signal as extern "C" fn(c_int) as usize) };
+
+        if previous == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn install_handlers() -> io::Result<()> {
+    // no std-only way to hook ctrl-c elsewhere, so only the admin endpoint can stop the server
+    Ok(())
+}
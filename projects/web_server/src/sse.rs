@@ -0,0 +1,613 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    io::{self, BufWriter, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    log,
+    request::{Method, Request},
+    response::{Response, StatusCode, Upgrade},
+};
+
+// Server-Sent Events push a stream of events down a response that never ends,
+// which a browser reads with `new EventSource(url)`:
+// ```
+// id: 42
+// event: price
+// data: {"symbol":"ACME","price":12.5}
+//
+// : heartbeat
+//
+// ```
+// a browser reconnects on its own when the stream breaks, sending the id of the last event it got
+// as `Last-Event-ID`, so a `Broadcaster` can send it what it missed
+
+// how often a comment is sent while there's nothing else to send
+// proxies close connections that look idle, and a client that's gone is only noticed on a write,
+// so this is also how long a stream's thread can outlast a client that went away
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+
+// a stream's thread only waits and formats events, so a small stack does,
+// which keeps thousands of open streams cheap
+const STREAM_STACK_SIZE: usize = 64 * 1024;
+
+// every open stream is a thread, so past this many, new ones are answered 503 instead
+const DEFAULT_MAX_STREAMS: usize = 1024;
+
+// the streams open in the whole process, `set_max_streams` changes the limit
+static STREAMS: StreamSlots = StreamSlots::new(DEFAULT_MAX_STREAMS);
+
+// how many events a `Broadcaster` keeps for clients that reconnect, unless told otherwise
+const DEFAULT_HISTORY: usize = 100;
+
+// one event in the stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    pub id: Option<String>,
+    // the type the browser dispatches it as, `message` if there's none
+    pub event: Option<String>,
+    pub data: String,
+    // how long the browser waits before reconnecting
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+// the event in the wire format, ending with the blank line that dispatches it
+// a line break in `data` starts another `data:` line, which the browser joins back with `\n`,
+// while line breaks in the id or type would end the field early, so they're left out
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let single_line = |value: &str| -> String {
+            value
+                .chars()
+                .filter(|c| !matches!(c, '\r' | '\n' | '\0'))
+                .collect()
+        };
+
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            writeln!(f, "data: {line}")?;
+        }
+
+        f.write_char('\n')
+    }
+}
+
+// how many event streams can be open at once, across every `EventStream` and `Broadcaster`
+// streams already open when it's lowered keep going, only new ones are turned away
+pub fn set_max_streams(max: usize) {
+    STREAMS.max.store(max, Ordering::Relaxed);
+}
+
+// the id of the last event a reconnecting client got
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.header("Last-Event-ID")
+}
+
+// a response that sends every event received from a channel, until every sender is dropped
+// ```
+// let (sender, receiver) = mpsc::channel();
+// jobs.subscribe(sender);
+// EventStream::new(receiver).into_response(request)
+// ```
+// once the head has been sent, the connection is handed from the worker to a thread of its own,
+// which sleeps on the channel between events, so open streams don't keep requests from being served
+// how many threads that can add up to is bounded by `set_max_streams`
+// it outlives the server too, dropping the senders, or closing the `Broadcaster`, is what ends it
+pub struct EventStream {
+    receiver: mpsc::Receiver<Event>,
+    heartbeat: Duration,
+    retry: Option<Duration>,
+}
+
+impl EventStream {
+    pub fn new(receiver: mpsc::Receiver<Event>) -> Self {
+        Self {
+            receiver,
+            heartbeat: DEFAULT_HEARTBEAT,
+            retry: None,
+        }
+    }
+
+    // how long the stream may go without sending anything, panics if it's zero
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "heartbeats need an interval above zero"
+        );
+        self.heartbeat = interval;
+        self
+    }
+
+    // tells the browser how long to wait before reconnecting, sent first thing
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // the streaming response for `request`
+    // its body ends where the connection does, which any HTTP/1.0 or 1.1 client understands
+    pub fn into_response(self, request: &Request) -> Response {
+        let response = Response::ok()
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache");
+
+        // the same head, and the connection closed right after it
+        if request.method == Method::Head {
+            return response.with_upgrade(Upgrade::new(|_, _| {}));
+        }
+
+        // taken now, so a client over the limit still gets a proper answer
+        let Some(slot) = STREAMS.take() else {
+            log::warn("Too many event streams open, refusing another");
+            return Response::new(StatusCode::ServiceUnavailable).with_header("Retry-After", "1");
+        };
+
+        response.with_upgrade(Upgrade::new(move |stream, _| self.spawn(stream, slot)))
+    }
+
+    fn spawn(self, stream: TcpStream, slot: StreamSlot<'static>) {
+        let started = thread::Builder::new()
+            .name(String::from("event-stream"))
+            .stack_size(STREAM_STACK_SIZE)
+            .spawn(move || {
+                let _slot = slot;
+
+                // the write timeout the connection set still applies,
+                // so a client that stops reading can't hold the thread either
+                if let Err(err) = self.run(&mut BufWriter::new(&stream)) {
+                    log::debug(format_args!("Event stream ended: {err}"));
+                }
+            });
+
+        // the stream was moved into the thread that couldn't start, dropping it closes the connection
+        if let Err(err) = started {
+            log::error(format_args!("Couldn't start an event stream: {err}"));
+        }
+    }
+
+    fn run(self, writer: &mut impl Write) -> io::Result<()> {
+        if let Some(retry) = self.retry {
+            write!(writer, "retry: {}\n\n", retry.as_millis())?;
+        }
+        // the client knows the stream works before the first event
+        writer.flush()?;
+
+        loop {
+            match self.receiver.recv_timeout(self.heartbeat) {
+                Ok(event) => {
+                    write!(writer, "{event}")?;
+
+                    // whatever else is waiting goes out in the same flush
+                    for event in self.receiver.try_iter() {
+                        write!(writer, "{event}")?;
+                    }
+                }
+                // fails once the client has gone, which ends the stream and its thread
+                Err(RecvTimeoutError::Timeout) => writer.write_all(b": heartbeat\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            writer.flush()?;
+        }
+    }
+}
+
+// counts open streams against a limit
+struct StreamSlots {
+    open: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl StreamSlots {
+    const fn new(max: usize) -> Self {
+        Self {
+            open: AtomicUsize::new(0),
+            max: AtomicUsize::new(max),
+        }
+    }
+
+    fn take(&self) -> Option<StreamSlot<'_>> {
+        let max = self.max.load(Ordering::Relaxed);
+
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| StreamSlot { slots: self })
+    }
+}
+
+// one open stream, given back when its thread ends, or when the response is dropped before it starts
+struct StreamSlot<'a> {
+    slots: &'a StreamSlots,
+}
+
+impl Drop for StreamSlot<'_> {
+    fn drop(&mut self) {
+        self.slots.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// sends every event to every client subscribed to it, and keeps the last few for clients
+// that reconnect with a `Last-Event-ID`
+// ```
+// let prices = Broadcaster::new();
+// router.get("/prices", {
+//     let prices = prices.clone();
+//     move |request, _| prices.subscribe(request)
+// });
+// prices.send(Event::new("12.5").with_event("price"));
+// ```
+// cheap to clone, and every clone sends to the same clients
+#[derive(Clone)]
+pub struct Broadcaster {
+    shared: Arc<Mutex<BroadcastState>>,
+    heartbeat: Duration,
+}
+
+struct BroadcastState {
+    subscribers: Vec<mpsc::Sender<Event>>,
+    // oldest first
+    history: VecDeque<Event>,
+    capacity: usize,
+    next_id: u64,
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Self::with_history(DEFAULT_HISTORY)
+    }
+
+    // keeps the last `events` events for reconnecting clients, 0 to keep none
+    pub fn with_history(events: usize) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(BroadcastState {
+                subscribers: Vec::new(),
+                history: VecDeque::with_capacity(events),
+                capacity: events,
+                next_id: 1,
+            })),
+            heartbeat: DEFAULT_HEARTBEAT,
+        }
+    }
+
+    // the heartbeat of the streams `subscribe` makes from here on
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "heartbeats need an interval above zero"
+        );
+        self.heartbeat = interval;
+        self
+    }
+
+    // sends `event` to every client, numbering it first if it has no id,
+    // so clients that reconnect can say where they were
+    pub fn send(&self, mut event: Event) {
+        let mut state = self.lock();
+
+        if event.id.is_none() {
+            event.id = Some(state.next_id.to_string());
+            state.next_id += 1;
+        }
+
+        if state.capacity > 0 {
+            if state.history.len() == state.capacity {
+                state.history.pop_front();
+            }
+            state.history.push_back(event.clone());
+        }
+
+        // the streams of clients that have gone are dropped, and with them their receivers
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    // a stream of everything sent from now on, for `request`
+    // starts with the events after its `Last-Event-ID`, or every event kept if that one is too old
+    pub fn subscribe(&self, request: &Request) -> Response {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self.lock();
+
+        if let Some(last) = last_event_id(request) {
+            let missed = match state
+                .history
+                .iter()
+                .position(|event| event.id.as_deref() == Some(last))
+            {
+                Some(position) => position + 1,
+                None => 0,
+            };
+
+            for event in state.history.range(missed..) {
+                // the receiver is right here, it can't have gone
+                let _ = sender.send(event.clone());
+            }
+        }
+
+        // still under the lock, so nothing sent in between is missed or sent twice
+        state.subscribers.push(sender);
+        drop(state);
+
+        EventStream::new(receiver)
+            .with_heartbeat(self.heartbeat)
+            .into_response(request)
+    }
+
+    // how many clients are subscribed, counting ones that left since the last `send`
+    pub fn subscribers(&self) -> usize {
+        self.lock().subscribers.len()
+    }
+
+    // ends every stream, the clients are free to reconnect
+    pub fn close(&self) {
+        self.lock().subscribers.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BroadcastState> {
+        // nothing in here can panic halfway through changing the state
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Read,
+        net::TcpListener,
+        time::{Duration, Instant},
+    };
+
+    // hands the server end of a connection to the stream, and returns the client's end
+    fn connect(response: Response) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        response.upgrade.unwrap().run(server, Vec::new());
+        client
+    }
+
+    // everything sent until the stream ends
+    fn events(response: Response) -> String {
+        let mut events = String::new();
+        connect(response).read_to_string(&mut events).unwrap();
+        events
+    }
+
+    fn request(last_event_id: Option<&str>) -> Request {
+        let mut request = Request::new(Method::Get, "/events");
+        if let Some(id) = last_event_id {
+            request.headers.set("Last-Event-ID", id);
+        }
+        request
+    }
+
+    #[test]
+    fn event_format() {
+        let event = Event::new("first line\nsecond\r\nthird")
+            .with_id("7")
+            .with_event("update")
+            .with_retry(Duration::from_secs(3));
+
+        assert_eq!(
+            event.to_string(),
+            "id: 7\nevent: update\nretry: 3000\n\
+             data: first line\ndata: second\ndata: third\n\n"
+        );
+
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+
+        let sneaky = Event::new("x").with_id("1\n\ndata: injected");
+        assert_eq!(sneaky.to_string(), "id: 1data: injected\ndata: x\n\n");
+    }
+
+    #[test]
+    fn streams_over_the_limit_are_refused() {
+        let slots = StreamSlots::new(2);
+
+        let first = slots.take();
+        let second = slots.take();
+        assert!(first.is_some() && second.is_some());
+        assert!(slots.take().is_none());
+
+        // a stream that ends makes room for the next one
+        drop(first);
+        assert!(slots.take().is_some());
+        assert_eq!(slots.open.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn streams_the_channel_until_it_closes() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Event::new("one")).unwrap();
+        sender.send(Event::new("two").with_event("second")).unwrap();
+        drop(sender);
+
+        let response = EventStream::new(receiver)
+            .with_retry(Duration::from_millis(500))
+            .into_response(&request(None));
+
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/event-stream")
+        );
+        assert_eq!(response.headers.get("Cache-Control"), Some("no-cache"));
+
+        assert_eq!(
+            events(response),
+            "retry: 500\n\ndata: one\n\nevent: second\ndata: two\n\n"
+        );
+    }
+
+    #[test]
+    fn heartbeats_while_nothing_happens() {
+        let (sender, receiver) = mpsc::channel();
+
+        let client = connect(
+            EventStream::new(receiver)
+                .with_heartbeat(Duration::from_millis(50))
+                .into_response(&request(None)),
+        );
+
+        thread::sleep(Duration::from_millis(200));
+        sender.send(Event::new("finally")).unwrap();
+        drop(sender);
+
+        let mut events = String::new();
+        (&client).read_to_string(&mut events).unwrap();
+        assert!(events.starts_with(": heartbeat\n\n"), "{events:?}");
+        assert!(events.ends_with("data: finally\n\n"), "{events:?}");
+    }
+
+    #[test]
+    fn a_gone_client_ends_the_stream_at_the_next_heartbeat() {
+        let (sender, receiver) = mpsc::channel();
+
+        let client = connect(
+            EventStream::new(receiver)
+                .with_heartbeat(Duration::from_millis(20))
+                .into_response(&request(None)),
+        );
+        drop(client);
+
+        // the thread drops the receiver when it ends
+        let started = Instant::now();
+        while sender.send(Event::new("anyone?")).is_ok() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "still streaming"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn head_gets_no_events() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Event::new("not for HEAD")).unwrap();
+
+        let mut head = request(None);
+        head.method = Method::Head;
+
+        let response = EventStream::new(receiver).into_response(&head);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/event-stream")
+        );
+        assert_eq!(events(response), "");
+    }
+
+    #[test]
+    fn broadcasts_numbered_events_to_every_subscriber() {
+        let broadcaster = Broadcaster::new();
+        let first = broadcaster.subscribe(&request(None));
+        let second = broadcaster.subscribe(&request(None));
+        assert_eq!(broadcaster.subscribers(), 2);
+
+        broadcaster.send(Event::new("a"));
+        broadcaster.send(Event::new("b").with_id("custom"));
+        broadcaster.close();
+
+        let expected = "id: 1\ndata: a\n\nid: custom\ndata: b\n\n";
+        assert_eq!(events(first), expected);
+        assert_eq!(events(second), expected);
+    }
+
+    #[test]
+    fn reconnecting_clients_get_what_they_missed() {
+        let broadcaster = Broadcaster::with_history(3);
+        for data in ["a", "b", "c", "d"] {
+            broadcaster.send(Event::new(data));
+        }
+        // 1 has fallen out of the history, 2 to 4 are kept
+
+        let resumed = broadcaster.subscribe(&request(Some("2")));
+        let too_old = broadcaster.subscribe(&request(Some("1")));
+        let up_to_date = broadcaster.subscribe(&request(Some("4")));
+        let new = broadcaster.subscribe(&request(None));
+
+        broadcaster.send(Event::new("e"));
+        broadcaster.close();
+
+        assert_eq!(
+            events(resumed),
+            "id: 3\ndata: c\n\nid: 4\ndata: d\n\nid: 5\ndata: e\n\n"
+        );
+        assert_eq!(
+            events(too_old),
+            "id: 2\ndata: b\n\nid: 3\ndata: c\n\nid: 4\ndata: d\n\nid: 5\ndata: e\n\n"
+        );
+        assert_eq!(events(up_to_date), "id: 5\ndata: e\n\n");
+        assert_eq!(events(new), "id: 5\ndata: e\n\n");
+    }
+
+    #[test]
+    fn subscribers_that_left_are_dropped() {
+        let broadcaster = Broadcaster::new();
+        let kept = broadcaster.subscribe(&request(None));
+        drop(broadcaster.subscribe(&request(None)));
+        assert_eq!(broadcaster.subscribers(), 2);
+
+        broadcaster.send(Event::new("hello"));
+        assert_eq!(broadcaster.subscribers(), 1);
+
+        broadcaster.close();
+        assert_eq!(events(kept), "id: 1\ndata: hello\n\n");
+    }
+}
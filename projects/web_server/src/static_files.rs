@@ -0,0 +1,653 @@
+use std::{
+    fs::{File, Metadata},
+    io::{BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    date::UtcDateTime,
+    middleware::random_u64,
+    range,
+    request::{Method, Request},
+    response::{Body, Response, StatusCode},
+};
+
+// serves files from a document root directory
+// mount it on a wildcard route and pass the request and the captured tail to `serve_request`:
+// ```
+// let files = StaticFiles::new("public");
+// router.get("/static/*path", move |request, params| {
+//     files.serve_request(request, params.get("path").unwrap_or(""))
+// })
+// ```
+// every file is sent with an `ETag` and a `Last-Modified`, so browsers can ask whether it changed
+// and get a 304 Not Modified instead of the file again, and `Range` requests get just the bytes asked for
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index: String::from("index.html"),
+        }
+    }
+
+    // the file served when a directory is requested
+    pub fn with_index(mut self, index: impl Into<String>) -> Self {
+        self.index = index.into();
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // `url_path` is relative to the root, percent-encoded as it appeared in the request
+    // always sends the whole file, see `serve_request` for one that looks at the request's headers
+    pub fn serve(&self, url_path: &str) -> Response {
+        self.respond(None, url_path)
+    }
+
+    // like `serve`, but answers conditional requests with 304 Not Modified
+    // and range requests with 206 Partial Content
+    pub fn serve_request(&self, request: &Request, url_path: &str) -> Response {
+        self.respond(Some(request), url_path)
+    }
+
+    fn respond(&self, request: Option<&Request>, url_path: &str) -> Response {
+        let Some(relative) = sanitize(url_path) else {
+            return Response::new(StatusCode::Forbidden);
+        };
+
+        let Some(path) = self.resolve(&relative) else {
+            return Response::not_found();
+        };
+
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => return Response::not_found(),
+        };
+
+        let metadata = match file.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => return Response::new(StatusCode::InternalServerError),
+        };
+
+        let len = metadata.len();
+        let content_type = content_type(&path);
+        let validators = Validators::of(&metadata);
+
+        if request.is_some_and(|request| validators.not_modified(request)) {
+            return validators.add_to(Response::new(StatusCode::NotModified));
+        }
+
+        let ranges = request.and_then(|request| requested_ranges(request, &validators, len));
+
+        let response = match ranges.as_deref() {
+            None => Response::ok()
+                .with_header("Content-Type", content_type)
+                .with_body(Body::from_reader(BufReader::new(file), len)),
+            Some([]) => Response::new(StatusCode::RangeNotSatisfiable)
+                .with_header("Content-Range", format!("bytes */{len}")),
+            Some([range]) => {
+                let mut file = file;
+                if file.seek(SeekFrom::Start(range.start)).is_err() {
+                    return Response::new(StatusCode::InternalServerError);
+                }
+
+                Response::new(StatusCode::PartialContent)
+                    .with_header("Content-Type", content_type)
+                    .with_header("Content-Range", range::content_range(range, len))
+                    .with_body(Body::from_reader(
+                        BufReader::new(file),
+                        range.end - range.start,
+                    ))
+            }
+            Some(ranges) => {
+                let boundary = boundary();
+                let body = range::multipart(file, ranges, len, content_type, &boundary);
+
+                Response::new(StatusCode::PartialContent)
+                    .with_header(
+                        "Content-Type",
+                        format!("multipart/byteranges; boundary={boundary}"),
+                    )
+                    .with_body(body)
+            }
+        };
+
+        validators.add_to(response.with_header("Accept-Ranges", "bytes"))
+    }
+
+    // turns the sanitized relative path into a file inside the root
+    fn resolve(&self, relative: &Path) -> Option<PathBuf> {
+        let root = self.root.canonicalize().ok()?;
+        let mut path = root.join(relative).canonicalize().ok()?;
+        // canonicalizing resolves symlinks, so a link pointing outside the root is caught below
+
+        if path.is_dir() {
+            // the index can be a link of its own
+            path = path.join(&self.index).canonicalize().ok()?;
+        }
+
+        if !path.starts_with(&root) {
+            return None;
+        }
+
+        path.is_file().then_some(path)
+    }
+}
+
+// what a client can remember a file by, to ask later whether it has changed
+struct Validators {
+    // `"<modified in nanoseconds>-<length>"` in hex, which changes whenever the file is written,
+    // without having to read the whole file to hash it
+    etag: String,
+    // seconds since the epoch, as precise as `Last-Modified` gets
+    modified: Option<u64>,
+}
+
+impl Validators {
+    fn of(metadata: &Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+
+        let nanos = modified.map_or(0, |modified| modified.as_nanos());
+
+        Self {
+            etag: format!("\"{nanos:x}-{:x}\"", metadata.len()),
+            modified: modified.map(|modified| modified.as_secs()),
+        }
+    }
+
+    fn add_to(&self, mut response: Response) -> Response {
+        response.headers.set("ETag", self.etag.clone());
+
+        if let Some(modified) = self.modified {
+            let date = UtcDateTime::from_unix_seconds(modified);
+            response
+                .headers
+                .set("Last-Modified", date.http_date().to_string());
+        }
+
+        response
+    }
+
+    // whether the client's copy is still current, so a 304 will do
+    // `If-None-Match` wins over `If-Modified-Since` when both are sent, it's the more precise of the two
+    fn not_modified(&self, request: &Request) -> bool {
+        if !matches!(request.method, Method::Get | Method::Head) {
+            return false;
+        }
+
+        if let Some(tags) = request.header("If-None-Match") {
+            return tags.split(',').map(str::trim).any(|tag| {
+                // any version of the file matches `*`, and a weak tag is as good as a strong one here
+                tag == "*" || weak_tag(tag) == weak_tag(&self.etag)
+            });
+        }
+
+        let since = request
+            .header("If-Modified-Since")
+            .and_then(UtcDateTime::parse_http_date);
+
+        match (self.modified, since) {
+            (Some(modified), Some(since)) => modified <= since.to_unix_seconds(),
+            _ => false,
+        }
+    }
+
+    // whether the file is still the version `If-Range` names, so its ranges can be sent
+    // otherwise the ranges would be of a different file than the client already has a part of,
+    // and the whole file is sent instead
+    fn still_matches(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            // only a strong tag is good enough to splice bytes together
+            return if_range == self.etag;
+        }
+
+        match (self.modified, UtcDateTime::parse_http_date(if_range)) {
+            (Some(modified), Some(date)) => modified == date.to_unix_seconds(),
+            _ => false,
+        }
+    }
+}
+
+// the tag without the `W/` that marks it as weak
+fn weak_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+// the ranges a GET request asks for, if they're to be honoured, see `range::parse`
+fn requested_ranges(
+    request: &Request,
+    validators: &Validators,
+    len: u64,
+) -> Option<Vec<std::ops::Range<u64>>> {
+    if request.method != Method::Get {
+        return None;
+    }
+
+    let header = request.header("Range")?;
+
+    if let Some(if_range) = request.header("If-Range") {
+        if !validators.still_matches(if_range) {
+            return None;
+        }
+    }
+
+    range::parse(header, len)
+}
+
+// separates the parts of a multipart/byteranges body, so it must not turn up inside the file
+// a random one is as good as guaranteed not to
+fn boundary() -> String {
+    format!("web_server_{:016x}", random_u64())
+}
+
+// decodes the url path into a relative file system path
+// returns `None` for anything that would escape the root, like `..` or an encoded `/`
+fn sanitize(url_path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for segment in url_path.split('/') {
+        let segment = percent_decode(segment)?;
+
+        match segment.as_str() {
+            "" | "." => continue,
+            ".." => return None,
+            _ => {}
+        }
+
+        if segment.contains(['/', '\\', '\0']) || Path::new(&segment).is_absolute() {
+            return None;
+        }
+
+        relative.push(segment);
+    }
+
+    Some(relative)
+}
+
+// decodes `%XX` escapes, returning `None` for a broken escape or invalid utf-8
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// guesses the Content-Type from the file extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env, fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // a fresh directory under the system temp dir, removed again when dropped
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+            let dir = env::temp_dir().join(format!(
+                "web_server_static_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(dir.join("docs")).unwrap();
+            fs::write(dir.join("index.html"), "<h1>root</h1>").unwrap();
+            fs::write(dir.join("docs").join("index.html"), "<h1>docs</h1>").unwrap();
+            fs::write(dir.join("docs").join("my notes.txt"), "notes").unwrap();
+            fs::write(dir.join("logo.PNG"), [0x89, b'P', b'N', b'G']).unwrap();
+
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        response.body.into_bytes().unwrap()
+    }
+
+    #[test]
+    fn serves_files_with_content_type() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        let response = files.serve("logo.PNG");
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get("Content-Type"), Some("image/png"));
+        assert_eq!(response.body.len(), Some(4));
+        assert_eq!(body(response), [0x89, b'P', b'N', b'G']);
+
+        let response = files.serve("docs/my%20notes.txt");
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(body(response), b"notes");
+    }
+
+    #[test]
+    fn directories_serve_index() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        assert_eq!(body(files.serve("")), b"<h1>root</h1>");
+        assert_eq!(body(files.serve("docs/")), b"<h1>docs</h1>");
+        assert_eq!(body(files.serve("docs")), b"<h1>docs</h1>");
+    }
+
+    #[test]
+    fn missing_files_are_not_found() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        assert_eq!(files.serve("nope.html").status, StatusCode::NotFound);
+
+        let files = StaticFiles::new(&root.0).with_index("default.html");
+        assert_eq!(files.serve("docs").status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn rejects_path_traversal() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(root.0.join("docs"));
+
+        for path in [
+            "../index.html",
+            "docs/../../index.html",
+            "%2e%2e/index.html",
+            "..%2findex.html",
+            "..%5cindex.html",
+            "bad%zz",
+        ] {
+            assert_eq!(
+                files.serve(path).status,
+                StatusCode::Forbidden,
+                "{path:?} should be rejected"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_out_of_the_root_are_not_followed() {
+        use std::os::unix::fs::symlink;
+
+        let root = TempRoot::new();
+        let docs = root.0.join("docs");
+        fs::create_dir(docs.join("linked")).unwrap();
+        symlink(
+            root.0.join("index.html"),
+            docs.join("linked").join("index.html"),
+        )
+        .unwrap();
+        symlink(root.0.join("logo.PNG"), docs.join("logo.png")).unwrap();
+
+        let files = StaticFiles::new(&docs);
+        for path in ["linked/", "linked/index.html", "logo.png"] {
+            assert_eq!(
+                files.serve(path).status,
+                StatusCode::NotFound,
+                "{path:?} should not be served"
+            );
+        }
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%4"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    fn get(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(Method::Get, "/");
+        for (name, value) in headers {
+            request.headers.set(*name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn sends_validators() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        let response = files.serve("index.html");
+        let etag = response.headers.get("ETag").unwrap();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+
+        let last_modified = response.headers.get("Last-Modified").unwrap();
+        assert!(UtcDateTime::parse_http_date(last_modified).is_some());
+
+        // rewriting the file with a different length changes the tag
+        fs::write(root.0.join("index.html"), "<h1>new root</h1>").unwrap();
+        assert_ne!(files.serve("index.html").headers.get("ETag"), Some(etag));
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        let etag = files
+            .serve("index.html")
+            .headers
+            .get("ETag")
+            .unwrap()
+            .to_string();
+
+        for if_none_match in [etag.clone(), format!("\"other\", W/{etag}"), "*".into()] {
+            let request = get(&[("If-None-Match", &if_none_match)]);
+            let response = files.serve_request(&request, "index.html");
+
+            assert_eq!(response.status, StatusCode::NotModified, "{if_none_match}");
+            assert_eq!(response.headers.get("ETag"), Some(etag.as_str()));
+            assert!(response.body.is_empty());
+        }
+
+        let request = get(&[("If-None-Match", "\"other\"")]);
+        assert_eq!(
+            files.serve_request(&request, "index.html").status,
+            StatusCode::Ok
+        );
+    }
+
+    #[test]
+    fn not_modified_since() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        let response = files.serve("index.html");
+        let last_modified = response.headers.get("Last-Modified").unwrap();
+
+        let request = get(&[("If-Modified-Since", last_modified)]);
+        let response = files.serve_request(&request, "index.html");
+        assert_eq!(response.status, StatusCode::NotModified);
+
+        let request = get(&[("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")]);
+        let response = files.serve_request(&request, "index.html");
+        assert_eq!(response.status, StatusCode::Ok);
+
+        // `If-None-Match` wins when both are sent
+        let request = get(&[
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", last_modified),
+        ]);
+        let response = files.serve_request(&request, "index.html");
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+
+    #[test]
+    fn single_range() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        // "<h1>root</h1>"
+        let request = get(&[("Range", "bytes=4-7")]);
+        let response = files.serve_request(&request, "index.html");
+
+        assert_eq!(response.status, StatusCode::PartialContent);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 4-7/13"));
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.body.len(), Some(4));
+        assert_eq!(body(response), b"root");
+
+        let request = get(&[("Range", "bytes=-5")]);
+        assert_eq!(body(files.serve_request(&request, "index.html")), b"</h1>");
+    }
+
+    #[test]
+    fn several_ranges_are_multipart() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        let request = get(&[("Range", "bytes=0-3,-5")]);
+        let response = files.serve_request(&request, "index.html");
+        assert_eq!(response.status, StatusCode::PartialContent);
+
+        let content_type = response.headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+
+        let text = String::from_utf8(body(response)).unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "\r\n--{boundary}\r\n\
+                 Content-Type: text/html; charset=utf-8\r\n\
+                 Content-Range: bytes 0-3/13\r\n\r\n\
+                 <h1>\
+                 \r\n--{boundary}\r\n\
+                 Content-Type: text/html; charset=utf-8\r\n\
+                 Content-Range: bytes 8-12/13\r\n\r\n\
+                 </h1>\
+                 \r\n--{boundary}--\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn range_past_the_end_is_not_satisfiable() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+
+        let request = get(&[("Range", "bytes=100-200")]);
+        let response = files.serve_request(&request, "index.html");
+
+        assert_eq!(response.status, StatusCode::RangeNotSatisfiable);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */13"));
+    }
+
+    #[test]
+    fn ranges_that_dont_apply_get_the_whole_file() {
+        let root = TempRoot::new();
+        let files = StaticFiles::new(&root.0);
+        let etag = files
+            .serve("index.html")
+            .headers
+            .get("ETag")
+            .unwrap()
+            .to_string();
+
+        for headers in [
+            vec![("Range", "lines=1-2")],
+            // the client's part is of a different version of the file
+            vec![("Range", "bytes=0-3"), ("If-Range", "\"stale\"")],
+            vec![
+                ("Range", "bytes=0-3"),
+                ("If-Range", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            ],
+        ] {
+            let response = files.serve_request(&get(&headers), "index.html");
+            assert_eq!(response.status, StatusCode::Ok, "{headers:?}");
+            assert_eq!(body(response), b"<h1>root</h1>");
+        }
+
+        let request = get(&[("Range", "bytes=0-3"), ("If-Range", &etag)]);
+        let response = files.serve_request(&request, "index.html");
+        assert_eq!(response.status, StatusCode::PartialContent);
+
+        // only GET has ranges
+        let mut request = get(&[("Range", "bytes=0-3")]);
+        request.method = Method::Head;
+        assert_eq!(
+            files.serve_request(&request, "index.html").status,
+            StatusCode::Ok
+        );
+    }
+}
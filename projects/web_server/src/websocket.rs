@@ -0,0 +1,717 @@
+use std::{
+    io::{self, BufRead, BufReader, Chain, Cursor, Read},
+    iter,
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use crate::{
+    connection::is_timeout,
+    log,
+    request::{Method, Request, Version},
+    response::{Response, StatusCode, Upgrade},
+};
+
+mod base64;
+pub(crate) mod frame;
+mod sha1;
+
+use frame::{FrameError, Opcode, MAX_CONTROL_PAYLOAD};
+
+// WebSockets (RFC 6455) turn an HTTP request into a connection both sides can send messages on
+// whenever they like, so the server can push live updates without the client polling:
+// ```
+// let router = Router::new().websocket("/echo", |mut socket: WebSocket| {
+//     let sender = socket.sender();
+//     for message in socket.messages() {
+//         if sender.send(message).is_err() {
+//             break;
+//         }
+//     }
+// });
+// ```
+// the handler runs on the worker that read the request, and keeps it for as long as the socket is open,
+// so a server expecting many sockets needs `with_max_workers` to leave room for plain requests
+
+// what a handshake key is combined with before hashing, the same for every server
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// close codes from RFC 6455 section 7.4.1
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_TOO_LARGE: u16 = 1009;
+
+// a client that hasn't sent anything for this long is pinged, and one that doesn't answer
+// by the next interval is taken to be gone
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+// once a frame has started, how long the rest of it may take
+const FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+// a message, put back together if it was sent in pieces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+// what a route added with `Router::websocket` runs for every connection
+// it's shared by every connection on the route, so it's `Fn` and `Sync` like a route handler
+pub trait WebSocketHandler: Send + Sync + 'static {
+    // returning closes the socket
+    fn handle(&self, socket: WebSocket);
+}
+
+impl<F> WebSocketHandler for F
+where
+    F: Fn(WebSocket) + Send + Sync + 'static,
+{
+    fn handle(&self, socket: WebSocket) {
+        self(socket)
+    }
+}
+
+// answers a WebSocket handshake, and runs `handler` on the socket once the response has been sent
+// a request that isn't a valid handshake is answered with 426 Upgrade Required or 400 Bad Request,
+// and `handler` isn't run
+// `Router::websocket` does this for a route, one whose handler needs the request or its params
+// can call it itself:
+// ```
+// .get("/rooms/:room", |request, params| {
+//     let room = params.get("room").unwrap_or_default().to_string();
+//     websocket::upgrade(request, move |socket| chat(room, socket))
+// })
+// ```
+pub fn upgrade<F>(request: &Request, handler: F) -> Response
+where
+    F: FnOnce(WebSocket) + Send + 'static,
+{
+    let key = match check_handshake(request) {
+        Ok(key) => key,
+        Err(response) => return response,
+    };
+
+    let upgrade = Upgrade::new(
+        move |stream, buffered| match WebSocket::new(stream, buffered) {
+            Ok(socket) => handler(socket),
+            Err(err) => log::debug(format_args!("Couldn't set up WebSocket: {err}")),
+        },
+    );
+
+    Response::new(StatusCode::SwitchingProtocols)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key))
+        .with_upgrade(upgrade)
+}
+
+// the client's key if `request` is a handshake this server can accept, otherwise the response to send
+fn check_handshake(request: &Request) -> Result<&str, Response> {
+    if !request.headers.has_token("Upgrade", "websocket") {
+        // a plain request for a WebSocket route, which says what it takes instead
+        return Err(Response::new(StatusCode::UpgradeRequired).with_header("Upgrade", "websocket"));
+    }
+
+    if request.method != Method::Get
+        || request.version != Version::Http11
+        || !request.headers.has_token("Connection", "Upgrade")
+    {
+        return Err(Response::new(StatusCode::BadRequest));
+    }
+
+    // 13 is the only version there has been since the RFC, the ones before were drafts
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        return Err(Response::new(StatusCode::UpgradeRequired)
+            .with_header("Upgrade", "websocket")
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+
+    // 16 random bytes in base64
+    let key = request.header("Sec-WebSocket-Key").unwrap_or("").trim();
+    if base64::decode(key).map(|key| key.len()) != Some(16) {
+        return Err(Response::new(StatusCode::BadRequest));
+    }
+
+    Ok(key)
+}
+
+// the `Sec-WebSocket-Accept` for a `Sec-WebSocket-Key`, which shows the client the server
+// understood the handshake, rather than being some cache that passed the request on
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1::sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+// the server's end of an open WebSocket
+// messages are read with `recv` or `messages`, which also answer pings and the client's close,
+// and sent with `send` or with a `Sender`, which other threads can use at the same time
+pub struct WebSocket {
+    // what the client sent with the handshake comes first, then the socket
+    reader: BufReader<Chain<Cursor<Vec<u8>>, TcpStream>>,
+    sender: Sender,
+    // the opcode and the payload so far of a message that's arriving in pieces
+    partial: Option<(Opcode, Vec<u8>)>,
+    ping_interval: Option<Duration>,
+    max_message_size: usize,
+    // a ping went out and nothing has come back since
+    awaiting_pong: bool,
+    // the connection is closed, `recv` has nothing more to give
+    done: bool,
+}
+
+impl WebSocket {
+    fn new(stream: TcpStream, buffered: Vec<u8>) -> io::Result<Self> {
+        let sender = Sender {
+            shared: Arc::new(SenderState {
+                stream: Mutex::new(stream.try_clone()?),
+                closing: AtomicBool::new(false),
+            }),
+        };
+
+        Ok(Self {
+            reader: BufReader::new(Cursor::new(buffered).chain(stream)),
+            sender,
+            partial: None,
+            ping_interval: Some(DEFAULT_PING_INTERVAL),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            awaiting_pong: false,
+            done: false,
+        })
+    }
+
+    // how long the client may stay quiet before it's pinged, `None` to never ping it
+    // without pings a client that vanished without closing keeps its worker until the server stops
+    pub fn with_ping_interval(mut self, interval: Option<Duration>) -> Self {
+        self.ping_interval = interval.filter(|interval| !interval.is_zero());
+        self
+    }
+
+    // a bigger message closes the socket with 1009
+    pub fn with_max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    // another handle to send on, for other threads
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    pub fn send(&self, message: Message) -> io::Result<()> {
+        self.sender.send(message)
+    }
+
+    pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        self.sender.close(code, reason)
+    }
+
+    // waits for the next message, `None` once the socket is closed, by either side or by an error
+    pub fn recv(&mut self) -> Option<Message> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.read_message();
+
+        if let Err(err) = &result {
+            log::debug(format_args!("WebSocket closed: {err}"));
+
+            let code = match err {
+                FrameError::Io(_) => None,
+                FrameError::Protocol(_) => Some(CLOSE_PROTOCOL_ERROR),
+                FrameError::InvalidText => Some(CLOSE_INVALID_DATA),
+                FrameError::TooLarge => Some(CLOSE_TOO_LARGE),
+            };
+
+            if let Some(code) = code {
+                let _ = self.sender.close(code, "");
+            }
+        }
+
+        match result {
+            Ok(Some(message)) => Some(message),
+            Ok(None) | Err(_) => {
+                self.finish();
+                None
+            }
+        }
+    }
+
+    // every message until the socket is closed
+    pub fn messages(&mut self) -> impl Iterator<Item = Message> + '_ {
+        iter::from_fn(|| self.recv())
+    }
+
+    // reads frames until a whole message is in, answering control frames on the way
+    // `None` once the connection is closed
+    fn read_message(&mut self) -> Result<Option<Message>, FrameError> {
+        loop {
+            if !self.wait_for_frame()? {
+                return Ok(None);
+            }
+
+            let so_far = self
+                .partial
+                .as_ref()
+                .map_or(0, |(_, payload)| payload.len());
+            let frame = frame::read_frame(&mut self.reader, self.max_message_size - so_far)?;
+
+            match frame.opcode {
+                Opcode::Ping => self.sender.write(Opcode::Pong, &frame.payload)?,
+                Opcode::Pong => {}
+                Opcode::Close => {
+                    let code = close_code(&frame.payload)?;
+                    // answered with the same code, unless this is the answer to ours
+                    self.sender.close(code.unwrap_or(CLOSE_NORMAL), "")?;
+                    return Ok(None);
+                }
+                Opcode::Text | Opcode::Binary => {
+                    if self.partial.is_some() {
+                        return Err(FrameError::Protocol(
+                            "new message before the last one ended",
+                        ));
+                    }
+
+                    if frame.fin {
+                        return message(frame.opcode, frame.payload).map(Some);
+                    }
+                    self.partial = Some((frame.opcode, frame.payload));
+                }
+                Opcode::Continuation => {
+                    let Some((_, payload)) = &mut self.partial else {
+                        return Err(FrameError::Protocol("continuation without a message"));
+                    };
+                    payload.extend_from_slice(&frame.payload);
+
+                    if frame.fin {
+                        let (opcode, payload) = self.partial.take().expect("checked above");
+                        return message(opcode, payload).map(Some);
+                    }
+                }
+            }
+        }
+    }
+
+    // waits for the next frame to start, pinging the client whenever it has been quiet for too long
+    // returns false if the connection was closed, or the client didn't answer a ping
+    fn wait_for_frame(&mut self) -> Result<bool, FrameError> {
+        loop {
+            self.stream().set_read_timeout(self.ping_interval)?;
+
+            match self.reader.fill_buf().map(|buffer| !buffer.is_empty()) {
+                Ok(false) => return Ok(false),
+                Ok(true) => {
+                    // anything at all shows the client is still there
+                    self.awaiting_pong = false;
+                    self.stream().set_read_timeout(Some(FRAME_TIMEOUT))?;
+                    return Ok(true);
+                }
+                Err(err) if is_timeout(&err) => {
+                    if self.awaiting_pong {
+                        let _ = self.sender.close(CLOSE_GOING_AWAY, "ping timeout");
+                        return Ok(false);
+                    }
+
+                    self.sender.write(Opcode::Ping, b"")?;
+                    self.awaiting_pong = true;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // nothing can be sent or received after this, which also stops any `Sender` elsewhere
+    fn finish(&mut self) {
+        self.done = true;
+        self.sender.shared.closing.store(true, Ordering::SeqCst);
+
+        let _ = self.stream().shutdown(Shutdown::Both);
+    }
+
+    fn stream(&self) -> &TcpStream {
+        self.reader.get_ref().get_ref().1
+    }
+}
+
+impl Drop for WebSocket {
+    // a handler returning is a normal close, as far as the client is concerned
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.sender.close(CLOSE_NORMAL, "");
+            self.finish();
+        }
+    }
+}
+
+fn message(opcode: Opcode, payload: Vec<u8>) -> Result<Message, FrameError> {
+    match opcode {
+        Opcode::Text => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| FrameError::InvalidText),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+// the code in a close frame's payload, which is optional, and may be followed by a reason in UTF-8
+fn close_code(payload: &[u8]) -> Result<Option<u16>, FrameError> {
+    let (code, reason) = match payload {
+        [] => return Ok(None),
+        [_] => return Err(FrameError::Protocol("close frame with half a code")),
+        [high, low, reason @ ..] => (u16::from_be_bytes([*high, *low]), reason),
+    };
+
+    // the rest are reserved, or only for reporting locally and never sent
+    let valid = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+    if !valid {
+        return Err(FrameError::Protocol("invalid close code"));
+    }
+
+    if std::str::from_utf8(reason).is_err() {
+        return Err(FrameError::InvalidText);
+    }
+
+    Ok(Some(code))
+}
+
+// sends messages on a WebSocket, from any thread
+// cheap to clone, and every clone sends on the same socket, a whole message at a time
+#[derive(Clone)]
+pub struct Sender {
+    shared: Arc<SenderState>,
+}
+
+struct SenderState {
+    stream: Mutex<TcpStream>,
+    // a close frame has been sent, so no more messages may be
+    closing: AtomicBool,
+}
+
+impl Sender {
+    pub fn send(&self, message: Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.send_text(&text),
+            Message::Binary(bytes) => self.send_binary(&bytes),
+        }
+    }
+
+    pub fn send_text(&self, text: &str) -> io::Result<()> {
+        self.send_data(Opcode::Text, text.as_bytes())
+    }
+
+    pub fn send_binary(&self, bytes: &[u8]) -> io::Result<()> {
+        self.send_data(Opcode::Binary, bytes)
+    }
+
+    // starts closing the socket, the one reading from it sees `None` once the client answers
+    // does nothing if it's closing already
+    // `reason` is cut short to fit a control frame
+    pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        if self.shared.closing.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write(Opcode::Close, &payload)
+    }
+
+    // true once the socket is closing or closed, when sending fails
+    pub fn is_closed(&self) -> bool {
+        self.shared.closing.load(Ordering::SeqCst)
+    }
+
+    fn send_data(&self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        if self.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the WebSocket is closed",
+            ));
+        }
+
+        self.write(opcode, payload)
+    }
+
+    fn write(&self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        // a write can't panic halfway through a frame, so a poisoned lock is still fine to use
+        let mut stream = self
+            .shared
+            .stream
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        frame::write_frame(&mut *stream, opcode, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame::tests::{client_frame, server_frame};
+    use std::{io::Write, net::TcpListener, thread, time::Instant};
+
+    // a connected client socket, and the server's end as a WebSocket that was handed `buffered`
+    fn pair(buffered: Vec<u8>) -> (TcpStream, WebSocket) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        (client, WebSocket::new(server, buffered).unwrap())
+    }
+
+    fn handshake(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(Method::Get, "/ws");
+        for (name, value) in [
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Version", "13"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ] {
+            request.headers.set(name, value);
+        }
+        for (name, value) in headers {
+            request.headers.set(*name, *value);
+        }
+        request
+    }
+
+    fn close_payload(code: u16) -> Vec<u8> {
+        code.to_be_bytes().to_vec()
+    }
+
+    // the code in the close frame the client got
+    fn closed_with(client: &mut TcpStream) -> u16 {
+        let (opcode, payload) = server_frame(client);
+        assert_eq!(opcode, Opcode::Close);
+        u16::from_be_bytes([payload[0], payload[1]])
+    }
+
+    #[test]
+    fn accept_key_from_the_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn handshake_is_accepted() {
+        let response = upgrade(&handshake(&[]), |_| {});
+
+        assert_eq!(response.status, StatusCode::SwitchingProtocols);
+        assert_eq!(response.headers.get("Upgrade"), Some("websocket"));
+        assert_eq!(response.headers.get("Connection"), Some("Upgrade"));
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert!(response.upgrade.is_some());
+    }
+
+    #[test]
+    fn bad_handshakes_are_refused() {
+        let plain = Request::new(Method::Get, "/ws");
+        let response = upgrade(&plain, |_| {});
+        assert_eq!(response.status, StatusCode::UpgradeRequired);
+        assert_eq!(response.headers.get("Upgrade"), Some("websocket"));
+        assert!(response.upgrade.is_none());
+
+        let old = upgrade(&handshake(&[("Sec-WebSocket-Version", "8")]), |_| {});
+        assert_eq!(old.status, StatusCode::UpgradeRequired);
+        assert_eq!(old.headers.get("Sec-WebSocket-Version"), Some("13"));
+
+        for request in [
+            handshake(&[("Sec-WebSocket-Key", "too short")]),
+            handshake(&[("Sec-WebSocket-Key", "c2hvcnQ=")]),
+            handshake(&[("Connection", "keep-alive")]),
+            {
+                let mut head = handshake(&[]);
+                head.method = Method::Head;
+                head
+            },
+        ] {
+            let response = upgrade(&request, |_| {});
+            assert_eq!(response.status, StatusCode::BadRequest);
+            assert!(response.upgrade.is_none());
+        }
+    }
+
+    #[test]
+    fn messages_are_put_back_together_around_pings() {
+        // the first frame came in with the handshake
+        let (mut client, mut socket) = pair(client_frame(false, Opcode::Text, b"Hel"));
+
+        client
+            .write_all(&client_frame(true, Opcode::Ping, b"are you there"))
+            .unwrap();
+        client
+            .write_all(&client_frame(true, Opcode::Continuation, b"lo"))
+            .unwrap();
+        client
+            .write_all(&client_frame(true, Opcode::Binary, &[1, 2, 3]))
+            .unwrap();
+
+        assert_eq!(socket.recv(), Some(Message::Text("Hello".to_string())));
+        assert_eq!(socket.recv(), Some(Message::Binary(vec![1, 2, 3])));
+        assert_eq!(
+            server_frame(&mut client),
+            (Opcode::Pong, b"are you there".to_vec())
+        );
+    }
+
+    #[test]
+    fn close_is_answered_and_ends_the_messages() {
+        let (mut client, mut socket) = pair(Vec::new());
+
+        client
+            .write_all(&client_frame(true, Opcode::Text, b"last words"))
+            .unwrap();
+        let mut close = close_payload(CLOSE_GOING_AWAY);
+        close.extend_from_slice(b"navigating away");
+        client
+            .write_all(&client_frame(true, Opcode::Close, &close))
+            .unwrap();
+
+        let messages: Vec<_> = socket.messages().collect();
+        assert_eq!(messages, [Message::Text("last words".to_string())]);
+
+        assert_eq!(closed_with(&mut client), CLOSE_GOING_AWAY);
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        assert!(socket.send(Message::Text("too late".to_string())).is_err());
+    }
+
+    #[test]
+    fn broken_frames_close_with_the_matching_code() {
+        let mut unmasked = vec![0x81, 0x02];
+        unmasked.extend_from_slice(b"hi");
+
+        let cases = [
+            (unmasked, CLOSE_PROTOCOL_ERROR),
+            (
+                client_frame(true, Opcode::Continuation, b"nothing to continue"),
+                CLOSE_PROTOCOL_ERROR,
+            ),
+            (
+                [
+                    client_frame(false, Opcode::Text, b"one"),
+                    client_frame(true, Opcode::Text, b"two"),
+                ]
+                .concat(),
+                CLOSE_PROTOCOL_ERROR,
+            ),
+            (
+                client_frame(true, Opcode::Close, &close_payload(1005)),
+                CLOSE_PROTOCOL_ERROR,
+            ),
+            (
+                client_frame(true, Opcode::Text, &[0xff, 0xfe]),
+                CLOSE_INVALID_DATA,
+            ),
+            (
+                [
+                    client_frame(false, Opcode::Binary, &[0; 60]),
+                    client_frame(true, Opcode::Continuation, &[0; 60]),
+                ]
+                .concat(),
+                CLOSE_TOO_LARGE,
+            ),
+        ];
+
+        for (bytes, code) in cases {
+            let (mut client, socket) = pair(bytes);
+            let mut socket = socket.with_max_message_size(100);
+
+            assert_eq!(socket.recv(), None);
+            assert_eq!(closed_with(&mut client), code);
+        }
+    }
+
+    #[test]
+    fn other_threads_push_through_a_sender() {
+        let (mut client, mut socket) = pair(Vec::new());
+        let sender = socket.sender();
+
+        let pusher = thread::spawn(move || {
+            for n in 0..3 {
+                sender.send_text(&format!("update {n}")).unwrap();
+            }
+            sender
+        });
+
+        for n in 0..3 {
+            assert_eq!(
+                server_frame(&mut client),
+                (Opcode::Text, format!("update {n}").into_bytes())
+            );
+        }
+
+        let sender = pusher.join().unwrap();
+        client
+            .write_all(&client_frame(
+                true,
+                Opcode::Close,
+                &close_payload(CLOSE_NORMAL),
+            ))
+            .unwrap();
+        assert_eq!(socket.recv(), None);
+
+        assert!(sender.is_closed());
+        assert!(sender.send_text("gone").is_err());
+    }
+
+    #[test]
+    fn quiet_clients_are_pinged_and_dropped_if_they_dont_answer() {
+        let (mut client, socket) = pair(Vec::new());
+        let mut socket = socket.with_ping_interval(Some(Duration::from_millis(50)));
+
+        let started = Instant::now();
+        let server = thread::spawn(move || socket.recv());
+
+        assert_eq!(server_frame(&mut client), (Opcode::Ping, Vec::new()));
+        // the answer stands in for anything else the client might send
+        client
+            .write_all(&client_frame(true, Opcode::Pong, b""))
+            .unwrap();
+
+        assert_eq!(server_frame(&mut client), (Opcode::Ping, Vec::new()));
+        assert_eq!(closed_with(&mut client), CLOSE_GOING_AWAY);
+
+        assert_eq!(server.join().unwrap(), None);
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn dropping_the_socket_closes_it() {
+        let (mut client, socket) = pair(Vec::new());
+        socket.send(Message::Binary(vec![9])).unwrap();
+        drop(socket);
+
+        assert_eq!(server_frame(&mut client), (Opcode::Binary, vec![9]));
+        assert_eq!(closed_with(&mut client), CLOSE_NORMAL);
+    }
+
+    #[test]
+    fn long_reasons_are_cut_at_a_character() {
+        let (mut client, socket) = pair(Vec::new());
+        socket.close(CLOSE_NORMAL, &"é".repeat(100)).unwrap();
+
+        let (_, payload) = server_frame(&mut client);
+        assert!(payload.len() <= MAX_CONTROL_PAYLOAD);
+        assert!(std::str::from_utf8(&payload[2..]).is_ok());
+    }
+}
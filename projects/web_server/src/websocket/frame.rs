@@ -0,0 +1,308 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+// a WebSocket frame on the wire (RFC 6455 section 5.2):
+// ```
+//  0               1               2               3
+// |F|R|R|R| opcode|M| length      | extended length, 0, 2 or 8 bytes
+// |I|S|S|S|       |A|             |
+// |N|V|V|V|       |S|             | masking key, 4 bytes if MASK is set
+// | |1|2|3|       |K|             | payload
+// ```
+// every frame from a client is masked, the payload XORed with the key, and none from a server is
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Opcode {
+    // the next piece of a message that was split over several frames
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    // control frames can come in between the pieces of a split message
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+// control frames have to fit the 7 bit length
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Debug)]
+pub(crate) struct Frame {
+    // whether this is the last piece of its message
+    pub(crate) fin: bool,
+    pub(crate) opcode: Opcode,
+    pub(crate) payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(crate) enum FrameError {
+    Io(io::Error),
+    // the client broke the protocol, answered with close code 1002
+    Protocol(&'static str),
+    // a text message or close reason that isn't UTF-8, answered with close code 1007
+    InvalidText,
+    // more than the limit on messages, answered with close code 1009
+    TooLarge,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(err) => write!(f, "{err}"),
+            FrameError::Protocol(problem) => write!(f, "protocol error: {problem}"),
+            FrameError::InvalidText => f.write_str("text that isn't UTF-8"),
+            FrameError::TooLarge => f.write_str("message too large"),
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+// reads a frame from a client and unmasks it
+// the payload is only read as it arrives, so a frame claiming to be huge can't make it allocate much
+pub(crate) fn read_frame(reader: &mut impl Read, max_payload: usize) -> Result<Frame, FrameError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+
+    let fin = head[0] & 0x80 != 0;
+
+    // only extensions use these, and none were agreed on
+    if head[0] & 0x70 != 0 {
+        return Err(FrameError::Protocol("reserved bits set"));
+    }
+
+    let opcode = Opcode::from_bits(head[0] & 0x0f).ok_or(FrameError::Protocol("unknown opcode"))?;
+
+    if head[1] & 0x80 == 0 {
+        return Err(FrameError::Protocol("unmasked frame from the client"));
+    }
+
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+
+    if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(FrameError::Protocol("control frame split or too long"));
+    }
+
+    if len > max_payload as u64 {
+        return Err(FrameError::TooLarge);
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+
+    let mut payload = Vec::new();
+    reader.take(len).read_to_end(&mut payload)?;
+
+    if payload.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+// writes a whole message as one unmasked frame, the way a server sends them
+pub(crate) fn write_frame(
+    writer: &mut impl Write,
+    opcode: Opcode,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode.bits());
+
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+
+    // in one write, so frames sent from different threads can't end up interleaved
+    writer.write_all(&frame)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // a frame the way a client sends it, masked
+    pub(crate) fn client_frame(fin: bool, opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![(u8::from(fin) << 7) | opcode.bits()];
+
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    // reads a frame the way a client would, unmasked
+    pub(crate) fn server_frame(reader: &mut impl Read) -> (Opcode, Vec<u8>) {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).unwrap();
+        assert_eq!(head[0] & 0xf0, 0x80, "final and no reserved bits");
+        assert_eq!(head[1] & 0x80, 0, "unmasked");
+
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len).unwrap();
+                usize::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                reader.read_exact(&mut len).unwrap();
+                u64::from_be_bytes(len) as usize
+            }
+            len => usize::from(len),
+        };
+
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).unwrap();
+        (Opcode::from_bits(head[0] & 0x0f).unwrap(), payload)
+    }
+
+    fn read(bytes: &[u8]) -> Result<Frame, FrameError> {
+        read_frame(&mut &bytes[..], 1 << 20)
+    }
+
+    #[test]
+    fn the_masked_hello_from_the_rfc() {
+        let bytes = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let frame = read(&bytes).unwrap();
+
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, Opcode::Text);
+        assert_eq!(frame.payload, b"Hello");
+    }
+
+    #[test]
+    fn every_length_encoding() {
+        for len in [0, 125, 126, 0xffff, 0x10000] {
+            let payload = vec![7; len];
+            let frame = read(&client_frame(false, Opcode::Binary, &payload)).unwrap();
+            assert!(!frame.fin);
+            assert_eq!(frame.payload, payload);
+
+            let mut written = Vec::new();
+            write_frame(&mut written, Opcode::Binary, &payload).unwrap();
+            assert_eq!(server_frame(&mut &written[..]), (Opcode::Binary, payload));
+        }
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let mut reserved = client_frame(true, Opcode::Text, b"hi");
+        reserved[0] |= 0x40;
+
+        let mut unmasked = vec![0x81, 0x02];
+        unmasked.extend_from_slice(b"hi");
+
+        let mut unknown = client_frame(true, Opcode::Text, b"hi");
+        unknown[0] = 0x83;
+
+        for bytes in [
+            reserved,
+            unmasked,
+            unknown,
+            client_frame(false, Opcode::Ping, b""),
+            client_frame(true, Opcode::Ping, &[0; 126]),
+        ] {
+            assert!(
+                matches!(read(&bytes), Err(FrameError::Protocol(_))),
+                "{bytes:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn too_large_and_too_short() {
+        let frame = client_frame(true, Opcode::Binary, &[0; 2000]);
+        assert!(matches!(
+            read_frame(&mut &frame[..], 1000),
+            Err(FrameError::TooLarge)
+        ));
+
+        let frame = client_frame(true, Opcode::Binary, &[0; 200]);
+        assert!(matches!(read(&frame[..100]), Err(FrameError::Io(_))));
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use web_server::{Broadcaster, Event, Response, Router, Server};
+
+fn connect(addr: SocketAddr, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    write!(stream, "GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    stream
+}
+
+fn read_all(mut stream: TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn saturated_server_answers_503() {
+    // the one worker is stuck in `/block` until `release` is used
+    let (started_sender, started) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+    let started_sender = Mutex::new(started_sender);
+    let released = Arc::new(Mutex::new(released));
+
+    let router =
+        Router::new()
+            .get("/", |_, _| Response::text("hello"))
+            .get("/block", move |_, _| {
+                started_sender.lock().unwrap().send(()).unwrap();
+                let _ = released.lock().unwrap().recv();
+                Response::text("unblocked")
+            });
+
+    let server = Server::bind("127.0.0.1:0", 1)
+        .unwrap()
+        .with_queue_capacity(1);
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let metrics = server.metrics();
+
+    let running = thread::spawn(move || server.run(router).unwrap());
+
+    let blocked = connect(addr, "/block");
+    started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // takes the only place in the queue
+    let queued = connect(addr, "/");
+
+    // nowhere left to put this one
+    let refused = read_all(connect(addr, "/"));
+    assert!(refused.starts_with("HTTP/1.1 503 Service Unavailable"));
+    assert!(refused.contains("Retry-After: 1\r\n"));
+    assert_eq!(metrics.stats().unwrap().rejected, 1);
+
+    release.send(()).unwrap();
+    assert!(read_all(blocked).ends_with("unblocked"));
+    assert!(read_all(queued).ends_with("hello"));
+
+    // with the backlog cleared, new connections are served again
+    assert!(read_all(connect(addr, "/")).ends_with("hello"));
+
+    shutdown.shutdown();
+    assert!(running.join().unwrap());
+}
+
+#[test]
+fn rejected_clients_that_stay_connected_do_not_slow_down_accepting() {
+    let (started_sender, started) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+    let started_sender = Mutex::new(started_sender);
+    let released = Mutex::new(released);
+    let router = Router::new().get("/block", move |_, _| {
+        started_sender.lock().unwrap().send(()).unwrap();
+        let _ = released.lock().unwrap().recv();
+        Response::text("unblocked")
+    });
+
+    let server = Server::bind("127.0.0.1:0", 1)
+        .unwrap()
+        .with_queue_capacity(1);
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let running = thread::spawn(move || server.run(router).unwrap());
+
+    let blocked = connect(addr, "/block");
+    started.recv_timeout(Duration::from_secs(5)).unwrap();
+    let queued = connect(addr, "/block");
+
+    // each of these reads its 503 but never closes, lingering on every one in turn would take seconds
+    let start = Instant::now();
+    let mut refused = Vec::new();
+    for _ in 0..20 {
+        let stream = connect(addr, "/block");
+        let mut response = String::new();
+        (&stream).read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        refused.push(stream);
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    release.send(()).unwrap();
+    release.send(()).unwrap();
+    assert!(read_all(blocked).ends_with("unblocked"));
+    assert!(read_all(queued).ends_with("unblocked"));
+
+    shutdown.shutdown();
+    assert!(running.join().unwrap());
+}
+
+#[test]
+fn idle_event_streams_leave_the_workers_free() {
+    let events = Broadcaster::new();
+    let router = Router::new()
+        .get("/", |_, _| Response::text("hello"))
+        .get("/events", {
+            let events = events.clone();
+            move |request, _| events.subscribe(request)
+        });
+
+    // a request that finds both workers taken waits in the queue, and never gets an answer
+    let server = Server::bind("127.0.0.1:0", 2)
+        .unwrap()
+        .with_queue_capacity(1);
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let running = thread::spawn(move || server.run(router).unwrap());
+
+    // as many streams as there are workers, all of them idle
+    let mut streams: Vec<_> = (0..2)
+        .map(|_| {
+            let mut stream = BufReader::new(connect(addr, "/events"));
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                stream.read_line(&mut head).unwrap();
+            }
+
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(head.contains("Content-Type: text/event-stream\r\n"));
+            assert!(head.contains("Connection: close\r\n"));
+            assert!(!head.contains("Transfer-Encoding"));
+            assert!(!head.contains("Content-Length"));
+            stream
+        })
+        .collect();
+
+    assert!(read_all(connect(addr, "/")).ends_with("hello"));
+
+    // and the streams are still being written
+    events.send(Event::new("still here"));
+    for stream in &mut streams {
+        let mut event = [0; 24];
+        stream.read_exact(&mut event).unwrap();
+        assert_eq!(&event, b"id: 1\ndata: still here\n\n");
+    }
+
+    events.close();
+    for stream in streams {
+        let mut rest = Vec::new();
+        stream.into_inner().read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    shutdown.shutdown();
+    assert!(running.join().unwrap());
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use web_server::{Body, Response, Router, Server, StatusCode};
+
+// sends one request on a fresh connection and returns the raw response
+fn request(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    write!(stream, "GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn routes() -> Router {
+    Router::new()
+        .get("/", |_, _| Response::text("hello"))
+        .get("/slow", |_, _| {
+            thread::sleep(Duration::from_millis(500));
+            Response::text("finally")
+        })
+}
+
+#[test]
+fn shuts_down_and_finishes_in_flight_requests() {
+    let server = Server::bind("127.0.0.1:0", 2).unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let running = thread::spawn(move || server.run(routes()).unwrap());
+
+    assert!(request(addr, "/").ends_with("hello"));
+
+    let slow = thread::spawn(move || request(addr, "/slow"));
+    thread::sleep(Duration::from_millis(100));
+    // give the slow request time to reach a worker
+
+    let start = Instant::now();
+    shutdown.shutdown();
+
+    assert!(running.join().unwrap(), "workers should finish in time");
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let response = slow.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("finally"));
+
+    assert!(
+        TcpStream::connect(addr).is_err(),
+        "listener should be closed"
+    );
+}
+
+#[test]
+fn idle_keep_alive_connections_do_not_delay_shutdown() {
+    let server = Server::bind("127.0.0.1:0", 2).unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let running = thread::spawn(move || server.run(routes()).unwrap());
+
+    // a keep-alive client that goes quiet after its first request
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(idle.try_clone().unwrap());
+    let mut status = String::new();
+    reader.read_line(&mut status).unwrap();
+    assert!(status.starts_with("HTTP/1.1 200 OK"));
+
+    let start = Instant::now();
+    shutdown.shutdown();
+
+    assert!(running.join().unwrap());
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn deadline_abandons_stuck_requests() {
+    let server = Server::bind("127.0.0.1:0", 1)
+        .unwrap()
+        .with_shutdown_timeout(Duration::from_millis(100));
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let router = Router::new().get("/stuck", |_, _| {
+        thread::sleep(Duration::from_secs(2));
+        Response::text("too late")
+    });
+    let running = thread::spawn(move || server.run(router).unwrap());
+
+    let _stuck = thread::spawn(move || request(addr, "/stuck"));
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    shutdown.shutdown();
+
+    assert!(
+        !running.join().unwrap(),
+        "the stuck worker should be reported"
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn periodic_tasks_run_until_shutdown() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&runs);
+
+    let server = Server::bind("127.0.0.1:0", 1).unwrap().with_periodic_task(
+        Duration::from_millis(10),
+        move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        },
+    );
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let running = thread::spawn(move || server.run(routes()).unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while runs.load(Ordering::SeqCst) < 3 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(runs.load(Ordering::SeqCst) >= 3);
+    // requests are still served in between
+    assert!(request(addr, "/").ends_with("hello"));
+
+    shutdown.shutdown();
+    assert!(running.join().unwrap());
+
+    let stopped_at = runs.load(Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runs.load(Ordering::SeqCst), stopped_at);
+    // the pool dropped the task and what it captured
+    assert_eq!(Arc::strong_count(&runs), 1);
+}
+
+#[test]
+fn panicking_handlers_still_close_their_connection() {
+    let server = Server::bind("127.0.0.1:0", 2).unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+
+    let router = Router::new()
+        .get("/panic", |_, _| panic!("handler gave up"))
+        .get("/panic-stream", |_, _| {
+            Response::new(StatusCode::Ok).with_body(Body::stream(|out| {
+                out.write_all(b"partial")?;
+                panic!("producer gave up")
+            }))
+        });
+    let running = thread::spawn(move || server.run(router).unwrap());
+
+    // `request` fails on its read timeout if the shutdown list kept the socket open
+    assert_eq!(request(addr, "/panic"), "");
+    let streamed = request(addr, "/panic-stream");
+    assert!(streamed.starts_with("HTTP/1.1 200 OK"));
+    assert!(
+        !streamed.ends_with("0\r\n\r\n"),
+        "the body must not look complete"
+    );
+
+    shutdown.shutdown();
+    assert!(running.join().unwrap());
+}